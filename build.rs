@@ -0,0 +1,97 @@
+use std::{
+    path::Path,
+    process::Command,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+fn main() {
+    println!("cargo:rustc-env=BUILD_TIMESTAMP={}", build_timestamp());
+    println!("cargo:rustc-env=GIT_BRANCH={}", git_branch());
+    for (name, version) in dependency_versions() {
+        let var = name.to_uppercase().replace('-', "_");
+        println!("cargo:rustc-env=DEP_VERSION_{var}={version}");
+    }
+
+    // emitting any `rerun-if` disables the default of rerunning on every change,
+    // so the sources have to be listed too to keep the build timestamp fresh
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    println!("cargo:rerun-if-env-changed=GIT_BRANCH");
+}
+
+fn build_timestamp() -> String {
+    // honoring SOURCE_DATE_EPOCH for reproducible builds
+    let timestamp = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<i64>().ok())
+        .unwrap_or_else(|| {
+            SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs() as i64)
+                .unwrap_or_default()
+        });
+    OffsetDateTime::from_unix_timestamp(timestamp)
+        .expect("invalid build timestamp")
+        .format(&Rfc3339)
+        .expect("failed to format build timestamp")
+}
+
+/// `GIT_BRANCH` if set (CI usually provides it), otherwise asks git.
+fn git_branch() -> String {
+    std::env::var("GIT_BRANCH")
+        .ok()
+        .filter(|branch| !branch.trim().is_empty())
+        .or_else(|| {
+            let output = Command::new("git")
+                .args(["rev-parse", "--abbrev-ref", "HEAD"])
+                .output()
+                .ok()?;
+            output
+                .status
+                .success()
+                .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        })
+        .filter(|branch| !branch.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Dependencies whose version is reported by `/admin/deps`.
+const REPORTED_DEPENDENCIES: &[&str] = &[
+    "axum",
+    "reqwest",
+    "shuttle-runtime",
+    "tokio",
+    "tracing",
+    "tracing-subscriber",
+];
+
+/// Versions of the reported dependencies, as resolved in `Cargo.lock`.
+fn dependency_versions() -> Vec<(&'static str, String)> {
+    let manifest_dir = std::env::var("CARGO_MANIFEST_DIR").expect("CARGO_MANIFEST_DIR not set");
+    let lockfile =
+        std::fs::read_to_string(Path::new(&manifest_dir).join("Cargo.lock")).unwrap_or_default();
+
+    REPORTED_DEPENDENCIES
+        .iter()
+        .map(|name| {
+            let version = locked_version(&lockfile, name).unwrap_or("unknown");
+            (*name, version.to_string())
+        })
+        .collect()
+}
+
+/// Finds the `version` following `name = "<name>"` in a `[[package]]` entry.
+fn locked_version<'a>(lockfile: &'a str, name: &str) -> Option<&'a str> {
+    let name_line = format!("name = \"{name}\"");
+    let mut lines = lockfile.lines();
+    lines.find(|line| line.trim() == name_line)?;
+    lines
+        .next()?
+        .trim()
+        .strip_prefix("version = \"")?
+        .strip_suffix('"')
+}
@@ -0,0 +1,531 @@
+use crate::{
+    build_info,
+    capture::{self, Capture},
+    config::Config,
+    datadog,
+    error::AppError,
+    secrets::SecretProvider,
+    signature,
+    state::SharedState,
+};
+use axum::{
+    body::Body,
+    extract::State,
+    http::{header, HeaderName, HeaderValue, Method, Request},
+    middleware::{self, Next},
+    response::{Html, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use tower::ServiceExt;
+use tracing::instrument;
+use tracing_subscriber::EnvFilter;
+
+/// Routes under `/admin`. All of them require the `ADMIN_TOKEN` as a bearer token.
+///
+/// The debugging ones are only added when `DEBUG_ENDPOINTS` is enabled.
+pub fn router(state: SharedState) -> Router<SharedState> {
+    let mut router = Router::new()
+        .route("/reload-secrets", post(reload_secrets))
+        .route("/captures", get(captures))
+        .route("/drain", post(drain));
+    if state.config().debug_endpoints {
+        router = router
+            .route("/datadog-validate", get(datadog_validate))
+            .route("/deps", get(deps))
+            .route("/dashboard", get(dashboard))
+            .route("/replay", post(replay));
+    }
+    router
+        .route_layer(middleware::from_fn(pretty_json))
+        .route_layer(middleware::from_fn_with_state(state, require_admin))
+}
+
+/// Checks the `ADMIN_TOKEN`, and the signature of the request when `ADMIN_HMAC_KEY` is set.
+async fn require_admin(
+    State(state): State<SharedState>,
+    req: Request<Body>,
+    next: Next<Body>,
+) -> Result<Response, AppError> {
+    let config = state.config();
+    let token = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match (token, &config.admin_token) {
+        (Some(token), Some(admin_token))
+            if signature::secrets_match(token.as_bytes(), admin_token.as_bytes()) => {}
+        _ => return Err(AppError::Unauthorized),
+    }
+
+    let Some(hmac_key) = config.admin_hmac_key else {
+        return Ok(next.run(req).await);
+    };
+    let (parts, body) = req.into_parts();
+    let body = hyper::body::to_bytes(body)
+        .await
+        .map_err(|e| AppError::BadRequest(format!("failed to read the request: {e}")))?;
+    signature::verify_admin_request(&state.admin_nonces, &hmac_key, &parts, &body)?;
+    Ok(next.run(Request::from_parts(parts, Body::from(body))).await)
+}
+
+/// Indents the JSON responses for browsers (`Accept: text/html`) or with `?pretty=1`.
+async fn pretty_json<B>(req: Request<B>, next: Next<B>) -> Response {
+    let pretty_query = req
+        .uri()
+        .query()
+        .is_some_and(|query| query.split('&').any(|pair| pair == "pretty=1"));
+    let accepts_html = req
+        .headers()
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"));
+    let response = next.run(req).await;
+    let is_json = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .is_some_and(|content_type| content_type == "application/json");
+    if !(pretty_query || accepts_html) || !is_json {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let body = match hyper::body::to_bytes(body).await {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to read the admin response");
+            return Response::from_parts(parts, axum::body::boxed(Body::empty()));
+        }
+    };
+    let body = serde_json::from_slice::<Value>(&body)
+        .ok()
+        .and_then(|value| serde_json::to_string_pretty(&value).ok())
+        .map_or_else(|| Body::from(body), Body::from);
+    parts.headers.remove(header::CONTENT_LENGTH);
+    Response::from_parts(parts, axum::body::boxed(body))
+}
+
+/// Re-reads the secrets and applies the ones that can be hot-reloaded, see
+/// [`Config::reload_from`].
+///
+/// The others (e.g. `DD_API_KEY`, baked into the Datadog layer at startup) are reported back
+/// as requiring a restart if they changed.
+#[instrument(skip(state))]
+async fn reload_secrets(State(state): State<SharedState>) -> Result<Json<Value>, AppError> {
+    state.secrets.refresh();
+    let new_config = Config::from_secrets(&state.secrets).map_err(AppError::Config)?;
+
+    let filter =
+        EnvFilter::try_new(&new_config.log_level).map_err(|e| AppError::Config(e.into()))?;
+    state
+        .log_reload
+        .reload(filter)
+        .map_err(|e| AppError::Config(e.into()))?;
+
+    let mut config = state.config.write().expect("config lock poisoned");
+    let requires_restart = config.reload_from(new_config);
+    tracing::info!(log_level = %config.log_level, ?requires_restart, "Secrets reloaded");
+
+    Ok(Json(json!({
+        "log_level": config.log_level,
+        "requires_restart": requires_restart,
+    })))
+}
+
+/// Makes `/ready` fail from now on, the server keeps serving. There's no way back: it's
+/// meant to be followed by a redeploy.
+async fn drain(State(state): State<SharedState>) -> Json<Value> {
+    if !state.drain() {
+        tracing::warn!("Draining, /ready now returns 503");
+    }
+    Json(json!({ "draining": true }))
+}
+
+/// Lists the requests recorded by the debug capture (see `CAPTURE_REQUESTS`).
+async fn captures(State(state): State<SharedState>) -> Json<Vec<Capture>> {
+    Json(state.captures.list())
+}
+
+/// Asks Datadog whether the configured API key is valid, using `DD_API_URL`.
+#[instrument(skip(state))]
+async fn datadog_validate(State(state): State<SharedState>) -> Result<Json<Value>, AppError> {
+    let config = state.config();
+    let valid =
+        datadog::validate_api_key(&state.http_client, &config.dd_api_url, &config.dd_api_key)
+            .await
+            .map_err(AppError::Datadog)?;
+    if !valid {
+        tracing::warn!(region = ?config.region, "Datadog rejected the API key");
+    }
+    Ok(Json(json!({ "valid": valid })))
+}
+
+/// Versions of the key dependencies compiled into the binary.
+async fn deps() -> Json<Value> {
+    let deps = build_info::DEPENDENCIES
+        .iter()
+        .map(|(name, version)| (name.to_string(), json!(version)))
+        .collect::<serde_json::Map<_, _>>();
+    Json(Value::Object(deps))
+}
+
+#[derive(Debug, Deserialize)]
+struct ReplayRequest {
+    #[serde(default = "default_replay_method")]
+    method: String,
+    path: String,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    #[serde(default)]
+    body: String,
+}
+
+fn default_replay_method() -> String {
+    "GET".to_string()
+}
+
+/// Sends the request through a fresh copy of the router (the same middlewares included) and
+/// returns what it answered.
+#[instrument(skip(state, replay), fields(method = %replay.method, path = %replay.path))]
+async fn replay(
+    State(state): State<SharedState>,
+    Json(replay): Json<ReplayRequest>,
+) -> Result<Json<Value>, AppError> {
+    let path = replay.path.split('?').next().unwrap_or_default();
+    if path.trim_end_matches('/') == "/admin/replay" {
+        return Err(AppError::BadRequest(
+            "/admin/replay can't be replayed".into(),
+        ));
+    }
+    let method = Method::from_bytes(replay.method.to_uppercase().as_bytes())
+        .map_err(|_| AppError::BadRequest(format!("invalid method: {}", replay.method)))?;
+
+    let mut request = Request::builder().method(method).uri(&replay.path);
+    for (name, value) in &replay.headers {
+        let name = HeaderName::try_from(name)
+            .map_err(|_| AppError::BadRequest(format!("invalid header name: {name}")))?;
+        let value = HeaderValue::try_from(value)
+            .map_err(|_| AppError::BadRequest(format!("invalid value for header {name}")))?;
+        request = request.header(name, value);
+    }
+    let request = request
+        .body(Body::from(replay.body))
+        .map_err(|e| AppError::BadRequest(format!("invalid request: {e}")))?;
+
+    let response = crate::build_router(state)
+        .oneshot(request)
+        .await
+        .unwrap_or_else(|never| match never {});
+    let status = response.status().as_u16();
+    let headers = response
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            let value = capture::sanitize_header_value(value).into_owned();
+            (name.to_string(), json!(value))
+        })
+        .collect::<serde_json::Map<_, _>>();
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| AppError::BadRequest(format!("failed to read the response: {e}")))?;
+
+    Ok(Json(json!({
+        "status": status,
+        "headers": headers,
+        "body": String::from_utf8_lossy(&body),
+    })))
+}
+
+/// Small HTML page with the state of the instance, for humans.
+async fn dashboard(State(state): State<SharedState>) -> Html<String> {
+    let config = state.config();
+    let rows = [
+        ("Version", env!("CARGO_PKG_VERSION").to_string()),
+        (
+            "Build",
+            format!("{} ({})", build_info::BUILD_TIMESTAMP, build_info::PROFILE),
+        ),
+        ("Branch", build_info::GIT_BRANCH.to_string()),
+        (
+            "Uptime",
+            format!("{}s", state.started_at.elapsed().as_secs()),
+        ),
+        ("Region", format!("{:?}", config.region)),
+        ("Env", config.dd_env.unwrap_or_else(|| "-".to_string())),
+        ("Log level", config.log_level),
+        ("Draining", state.is_draining().to_string()),
+        ("4xx last minute", state.client_errors.get().to_string()),
+        ("5xx last minute", state.server_errors.get().to_string()),
+    ];
+    let rows = rows
+        .iter()
+        .map(|(name, value)| format!("<tr><th>{name}</th><td>{}</td></tr>", escape_html(value)))
+        .collect::<String>();
+    Html(format!(
+        "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\"><title>{service}</title></head>\
+         <body><h1>{service}</h1><table>{rows}</table></body></html>\n",
+        service = escape_html(&config.dd_service),
+    ))
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, body_string, send, TestState};
+    use axum::http::StatusCode;
+    use std::path::PathBuf;
+
+    const TOKEN: &str = "admin-token";
+
+    /// A fresh `SECRETS_DIR` for the test.
+    fn secrets_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("admin-{test}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn admin_request(method: Method, uri: &str, token: Option<&str>) -> Request<Body> {
+        let mut req = Request::builder().method(method).uri(uri);
+        if let Some(token) = token {
+            req = req.header(header::AUTHORIZATION, format!("Bearer {token}"));
+        }
+        req.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn reload_secrets_applies_the_new_log_level() {
+        let dir = secrets_dir("log-level");
+        std::fs::write(dir.join("LOG_LEVEL"), "info").unwrap();
+        let test = TestState::new(test_support::secrets(&[
+            ("ADMIN_TOKEN", TOKEN),
+            ("SECRETS_DIR", dir.to_str().unwrap()),
+        ]));
+
+        std::fs::write(dir.join("LOG_LEVEL"), "debug").unwrap();
+        let req = admin_request(Method::POST, "/admin/reload-secrets", Some(TOKEN));
+        let response = send(test.router(), req).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(body["log_level"], "debug");
+        assert_eq!(test.state.config().log_level, "debug");
+        let filter = test.state.log_reload.with_current(|f| f.to_string());
+        assert_eq!(filter.unwrap(), "debug");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reload_secrets_reports_the_ones_requiring_a_restart() {
+        let dir = secrets_dir("restart");
+        std::fs::write(dir.join("DD_REGION"), "US1").unwrap();
+        let test = TestState::new(test_support::secrets(&[
+            ("ADMIN_TOKEN", TOKEN),
+            ("SECRETS_DIR", dir.to_str().unwrap()),
+        ]));
+
+        std::fs::write(dir.join("DD_REGION"), "EU").unwrap();
+        let req = admin_request(Method::POST, "/admin/reload-secrets", Some(TOKEN));
+        let body = body_string(send(test.router(), req).await).await;
+
+        let body: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(body["requires_restart"], json!(["DD_REGION"]));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn admin_routes_require_the_token() {
+        let test = test_support::state(&[("ADMIN_TOKEN", TOKEN)]);
+
+        for token in [None, Some("wrong-token"), Some("admin-toke")] {
+            let req = admin_request(Method::POST, "/admin/reload-secrets", token);
+            let response = send(test.router(), req).await;
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED, "{token:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn admin_routes_are_not_served_without_a_token() {
+        let test = test_support::state(&[]);
+
+        let req = admin_request(Method::POST, "/admin/reload-secrets", Some(TOKEN));
+        let response = send(test.router(), req).await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn datadog_validate_asks_the_configured_api() {
+        let dd_api_url = test_support::mock_datadog();
+        for (api_key, valid) in [("valid-key", true), ("wrong-key", false)] {
+            let test = test_support::state(&[
+                ("ADMIN_TOKEN", TOKEN),
+                ("DEBUG_ENDPOINTS", "true"),
+                ("DD_API_URL", &dd_api_url),
+                ("DD_API_KEY", api_key),
+            ]);
+
+            let req = admin_request(Method::GET, "/admin/datadog-validate", Some(TOKEN));
+            let response = send(test.router(), req).await;
+
+            assert_eq!(response.status(), StatusCode::OK);
+            let body: Value = serde_json::from_str(&body_string(response).await).unwrap();
+            assert_eq!(body, json!({ "valid": valid }));
+        }
+    }
+
+    #[tokio::test]
+    async fn datadog_validate_fails_when_datadog_does() {
+        let test = test_support::state(&[
+            ("ADMIN_TOKEN", TOKEN),
+            ("DEBUG_ENDPOINTS", "true"),
+            ("DD_API_URL", &test_support::mock_datadog()),
+            ("DD_API_KEY", "broken-key"),
+        ]);
+
+        let req = admin_request(Method::GET, "/admin/datadog-validate", Some(TOKEN));
+        let response = send(test.router(), req).await;
+
+        assert!(response.status().is_server_error());
+    }
+
+    #[tokio::test]
+    async fn deps_reports_the_compiled_versions() {
+        let test = test_support::state(&[("ADMIN_TOKEN", TOKEN), ("DEBUG_ENDPOINTS", "true")]);
+        // the lockfile the binary was built with
+        let lockfile = include_str!("../Cargo.lock");
+        let axum = lockfile
+            .split("[[package]]")
+            .find(|package| package.contains("name = \"axum\"\n"))
+            .and_then(|package| package.split("version = \"").nth(1))
+            .and_then(|version| version.split('"').next())
+            .unwrap();
+
+        let req = admin_request(Method::GET, "/admin/deps", Some(TOKEN));
+        let body = body_string(send(test.router(), req).await).await;
+
+        let deps: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(deps["axum"], axum);
+        assert!(axum.starts_with("0.6."));
+    }
+
+    #[tokio::test]
+    async fn debug_endpoints_are_disabled_by_default() {
+        let test = test_support::state(&[("ADMIN_TOKEN", TOKEN)]);
+
+        let req = admin_request(Method::GET, "/admin/deps", Some(TOKEN));
+        let response = send(test.router(), req).await;
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn drain_starts_draining() {
+        let test = test_support::state(&[("ADMIN_TOKEN", TOKEN)]);
+
+        for _ in 0..2 {
+            let req = admin_request(Method::POST, "/admin/drain", Some(TOKEN));
+            let body = body_string(send(test.router(), req).await).await;
+            assert_eq!(body, r#"{"draining":true}"#);
+        }
+        assert!(test.state.is_draining());
+    }
+
+    fn replay_request(replay: Value) -> Request<Body> {
+        Request::post("/admin/replay")
+            .header(header::AUTHORIZATION, format!("Bearer {TOKEN}"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(replay.to_string()))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn replay_returns_the_replayed_response() {
+        let test = test_support::state(&[("ADMIN_TOKEN", TOKEN), ("DEBUG_ENDPOINTS", "true")]);
+
+        let replay = json!({ "path": "/", "headers": { "x-feature-flags": "shout" } });
+        let response = send(test.router(), replay_request(replay)).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: Value = serde_json::from_str(&body_string(response).await).unwrap();
+        assert_eq!(body["status"], 200);
+        assert_eq!(body["body"], "HELLO, WORLD!");
+        assert!(body["headers"]["x-request-id"].is_string());
+    }
+
+    #[tokio::test]
+    async fn replay_refuses_to_replay_itself() {
+        let test = test_support::state(&[("ADMIN_TOKEN", TOKEN), ("DEBUG_ENDPOINTS", "true")]);
+
+        for path in ["/admin/replay", "/admin/replay/?again=1"] {
+            let replay = json!({ "method": "post", "path": path });
+            let response = send(test.router(), replay_request(replay)).await;
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn dashboard_summarizes_the_instance() {
+        let test = test_support::state(&[
+            ("ADMIN_TOKEN", TOKEN),
+            ("DEBUG_ENDPOINTS", "true"),
+            ("DD_REGION", "EU"),
+            ("DD_SERVICE", "<web>"),
+        ]);
+        let missing = admin_request(Method::GET, "/missing", None);
+        assert_eq!(
+            send(test.router(), missing).await.status(),
+            StatusCode::NOT_FOUND
+        );
+
+        let req = admin_request(Method::GET, "/admin/dashboard", Some(TOKEN));
+        let response = send(test.router(), req).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/html; charset=utf-8"
+        );
+        let html = body_string(response).await;
+        assert!(html.contains(&format!(
+            "<tr><th>Version</th><td>{}</td></tr>",
+            env!("CARGO_PKG_VERSION")
+        )));
+        assert!(html.contains("<tr><th>Region</th><td>EU</td></tr>"));
+        assert!(html.contains("<h1>&lt;web&gt;</h1>"));
+        assert!(html.contains("<tr><th>4xx last minute</th><td>1</td></tr>"));
+        assert!(html.contains("<tr><th>5xx last minute</th><td>0</td></tr>"));
+    }
+
+    #[tokio::test]
+    async fn json_is_pretty_printed_on_demand() {
+        let test = test_support::state(&[("ADMIN_TOKEN", TOKEN)]);
+        let pretty = "{\n  \"draining\": true\n}";
+
+        for (uri, accept, body) in [
+            ("/admin/drain", None, r#"{"draining":true}"#),
+            ("/admin/drain?pretty=1", None, pretty),
+            ("/admin/drain?verbose=1&pretty=1", None, pretty),
+            ("/admin/drain", Some("text/html,*/*"), pretty),
+        ] {
+            let mut req = admin_request(Method::POST, uri, Some(TOKEN));
+            if let Some(accept) = accept {
+                req.headers_mut()
+                    .insert(header::ACCEPT, HeaderValue::from_static(accept));
+            }
+            let response = send(test.router(), req).await;
+            assert_eq!(body_string(response).await, body, "{uri} {accept:?}");
+        }
+    }
+}
@@ -0,0 +1,41 @@
+//! Information captured by `build.rs`.
+
+/// When the binary was built, in RFC 3339.
+pub const BUILD_TIMESTAMP: &str = env!("BUILD_TIMESTAMP");
+
+/// Git branch the binary was built from, or `unknown`.
+pub const GIT_BRANCH: &str = env!("GIT_BRANCH");
+
+/// `debug` or `release`, depending on whether debug assertions are on.
+pub const PROFILE: &str = if cfg!(debug_assertions) {
+    "debug"
+} else {
+    "release"
+};
+
+/// Versions of the key dependencies, as resolved in `Cargo.lock` at build time.
+pub const DEPENDENCIES: &[(&str, &str)] = &[
+    ("axum", env!("DEP_VERSION_AXUM")),
+    ("reqwest", env!("DEP_VERSION_REQWEST")),
+    ("shuttle-runtime", env!("DEP_VERSION_SHUTTLE_RUNTIME")),
+    ("tokio", env!("DEP_VERSION_TOKIO")),
+    ("tracing", env!("DEP_VERSION_TRACING")),
+    ("tracing-subscriber", env!("DEP_VERSION_TRACING_SUBSCRIBER")),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profile_follows_debug_assertions() {
+        // tests are usually debug builds, but `cargo test --release` is a thing
+        let expected = if cfg!(debug_assertions) {
+            "debug"
+        } else {
+            "release"
+        };
+
+        assert_eq!(PROFILE, expected);
+    }
+}
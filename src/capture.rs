@@ -0,0 +1,229 @@
+use crate::state::SharedState;
+use axum::{
+    extract::State,
+    http::{HeaderMap, HeaderValue, Request},
+    middleware::Next,
+    response::Response,
+};
+use serde::Serialize;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, VecDeque},
+    sync::Mutex,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+/// Headers that are never captured nor echoed, even if they're in the allowlist.
+pub const SENSITIVE_HEADERS: &[&str] = &[
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "dd-api-key",
+    "x-api-key",
+];
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Capture {
+    pub timestamp_ms: u128,
+    pub method: String,
+    pub path: String,
+    pub request_headers: BTreeMap<String, String>,
+    pub status: u16,
+    pub response_headers: BTreeMap<String, String>,
+    pub duration_ms: u128,
+}
+
+/// Ring buffer keeping the last `capacity` captures.
+#[derive(Debug)]
+pub struct CaptureBuffer {
+    capacity: usize,
+    captures: Mutex<VecDeque<Capture>>,
+}
+
+impl CaptureBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            captures: Mutex::new(VecDeque::with_capacity(capacity)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
+    pub fn push(&self, capture: Capture) {
+        if !self.is_enabled() {
+            return;
+        }
+        let mut captures = self.captures.lock().expect("captures lock poisoned");
+        if captures.len() == self.capacity {
+            captures.pop_front();
+        }
+        captures.push_back(capture);
+    }
+
+    pub fn list(&self) -> Vec<Capture> {
+        let captures = self.captures.lock().expect("captures lock poisoned");
+        captures.iter().cloned().collect()
+    }
+}
+
+/// Records the request/response details into the [`CaptureBuffer`] of the state.
+pub async fn capture_requests<B>(
+    State(state): State<SharedState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let allowlist = state.config().capture_headers;
+    let timestamp_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let method = req.method().to_string();
+    let path = req.uri().path().to_string();
+    let request_headers = allowed_headers(req.headers(), &allowlist);
+
+    let start = Instant::now();
+    let response = next.run(req).await;
+
+    state.captures.push(Capture {
+        timestamp_ms,
+        method,
+        path,
+        request_headers,
+        status: response.status().as_u16(),
+        response_headers: allowed_headers(response.headers(), &allowlist),
+        duration_ms: start.elapsed().as_millis(),
+    });
+    response
+}
+
+fn allowed_headers(headers: &HeaderMap, allowlist: &[String]) -> BTreeMap<String, String> {
+    let mut allowed = BTreeMap::new();
+    for name in allowlist {
+        if SENSITIVE_HEADERS.contains(&name.as_str()) {
+            continue;
+        }
+        let values = headers
+            .get_all(name.as_str())
+            .iter()
+            .map(sanitize_header_value)
+            .collect::<Vec<_>>();
+        if !values.is_empty() {
+            allowed.insert(name.clone(), values.join(", "));
+        }
+    }
+    allowed
+}
+
+/// Header values are raw bytes and may not be valid UTF-8.
+///
+/// Invalid sequences are replaced with `U+FFFD` so they don't end up as mojibake downstream.
+/// Every header value we log goes through it (`tenant_id`, `response.content_type` and the
+/// captured headers). The rest of the fields come from `&str`s, which are always valid UTF-8,
+/// so there's nothing to sanitize on the way to Datadog.
+pub fn sanitize_header_value(value: &HeaderValue) -> Cow<'_, str> {
+    String::from_utf8_lossy(value.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, send};
+    use axum::body::Body;
+
+    fn capture(path: &str) -> Capture {
+        Capture {
+            timestamp_ms: 0,
+            method: "GET".to_string(),
+            path: path.to_string(),
+            request_headers: BTreeMap::new(),
+            status: 200,
+            response_headers: BTreeMap::new(),
+            duration_ms: 0,
+        }
+    }
+
+    fn paths(buffer: &CaptureBuffer) -> Vec<String> {
+        buffer
+            .list()
+            .into_iter()
+            .map(|capture| capture.path)
+            .collect()
+    }
+
+    #[test]
+    fn buffer_keeps_the_last_captures() {
+        let buffer = CaptureBuffer::new(2);
+
+        for path in ["/a", "/b", "/c"] {
+            buffer.push(capture(path));
+        }
+
+        assert_eq!(paths(&buffer), ["/b", "/c"]);
+    }
+
+    #[test]
+    fn disabled_buffer_keeps_nothing() {
+        let buffer = CaptureBuffer::new(0);
+
+        buffer.push(capture("/a"));
+
+        assert!(!buffer.is_enabled());
+        assert!(buffer.list().is_empty());
+    }
+
+    #[test]
+    fn sensitive_and_unlisted_headers_are_not_captured() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-tenant", HeaderValue::from_static("acme"));
+        headers.insert("authorization", HeaderValue::from_static("Bearer secret"));
+        headers.insert("x-other", HeaderValue::from_static("other"));
+        let allowlist = ["x-tenant".to_string(), "authorization".to_string()];
+
+        let allowed = allowed_headers(&headers, &allowlist);
+
+        assert_eq!(
+            allowed,
+            BTreeMap::from([("x-tenant".to_string(), "acme".to_string())])
+        );
+    }
+
+    #[test]
+    fn invalid_utf8_header_values_are_replaced() {
+        let value = HeaderValue::from_bytes(b"caf\xe9 ok").unwrap();
+
+        assert_eq!(sanitize_header_value(&value), "caf\u{fffd} ok");
+    }
+
+    #[test]
+    fn invalid_utf8_header_values_are_still_captured() {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-tenant", HeaderValue::from_bytes(b"\xffacme").unwrap());
+
+        let allowed = allowed_headers(&headers, &["x-tenant".to_string()]);
+
+        assert_eq!(allowed["x-tenant"], "\u{fffd}acme");
+    }
+
+    #[tokio::test]
+    async fn requests_are_captured() {
+        let test =
+            test_support::state(&[("CAPTURE_REQUESTS", "2"), ("CAPTURE_HEADERS", "x-tenant")]);
+
+        for path in ["/", "/missing", "/other"] {
+            let req = Request::get(path)
+                .header("x-tenant", "acme")
+                .body(Body::empty())
+                .unwrap();
+            send(test.router(), req).await;
+        }
+
+        let captures = test.state.captures.list();
+        assert_eq!(paths(&test.state.captures), ["/missing", "/other"]);
+        assert_eq!(captures[1].status, 404);
+        assert_eq!(captures[1].request_headers["x-tenant"], "acme");
+    }
+}
@@ -0,0 +1,204 @@
+use crate::{error::AppError, state::SharedState};
+use axum::{
+    extract::{ConnectInfo, State},
+    http::{HeaderMap, Request},
+    middleware::Next,
+    response::Response,
+};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Mutex,
+};
+
+/// Address of the client making the request.
+///
+/// When `trust_proxy` is set, it's the `X-Forwarded-For` hop appended by the outermost of the
+/// `trusted_proxies`, i.e. that many hops from the right. The ones on its left are sent by the
+/// client, which can put anything there. Otherwise, it's the peer address of the connection.
+pub fn client_ip<B>(req: &Request<B>, trust_proxy: bool, trusted_proxies: usize) -> Option<IpAddr> {
+    let forwarded = trust_proxy
+        .then(|| forwarded_for(req.headers(), trusted_proxies))
+        .flatten();
+    forwarded.or_else(|| {
+        req.extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip())
+    })
+}
+
+fn forwarded_for(headers: &HeaderMap, trusted_proxies: usize) -> Option<IpAddr> {
+    let hops = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .collect::<Vec<_>>();
+    // fewer hops than proxies, the header can't be trusted
+    let hop = hops.len().checked_sub(trusted_proxies)?;
+    hops[hop].parse().ok()
+}
+
+/// Counts the in-flight requests of each client.
+#[derive(Debug, Default)]
+pub struct ClientLimiter {
+    in_flight: Mutex<HashMap<IpAddr, usize>>,
+}
+
+impl ClientLimiter {
+    /// Registers a new request of `ip`, unless it already has `max` in flight.
+    ///
+    /// The request is unregistered when the returned guard is dropped.
+    fn acquire(&self, ip: IpAddr, max: usize) -> Option<InFlightGuard<'_>> {
+        let mut in_flight = self.in_flight.lock().expect("limiter lock poisoned");
+        let count = in_flight.entry(ip).or_default();
+        if *count >= max {
+            return None;
+        }
+        *count += 1;
+        Some(InFlightGuard { limiter: self, ip })
+    }
+
+    fn release(&self, ip: IpAddr) {
+        let mut in_flight = self.in_flight.lock().expect("limiter lock poisoned");
+        if let Some(count) = in_flight.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                in_flight.remove(&ip);
+            }
+        }
+    }
+}
+
+struct InFlightGuard<'a> {
+    limiter: &'a ClientLimiter,
+    ip: IpAddr,
+}
+
+impl Drop for InFlightGuard<'_> {
+    fn drop(&mut self) {
+        self.limiter.release(self.ip);
+    }
+}
+
+/// Rejects with `429` the requests of clients having `MAX_CONCURRENT_PER_CLIENT` in flight.
+pub async fn limit_per_client<B>(
+    State(state): State<SharedState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, AppError> {
+    let config = state.config();
+    let Some(ip) = client_ip(&req, config.trust_proxy, config.trusted_proxies) else {
+        return Ok(next.run(req).await);
+    };
+
+    let Some(_guard) = state
+        .client_limiter
+        .acquire(ip, config.max_concurrent_per_client)
+    else {
+        tracing::warn!(client.ip = %ip, "Too many concurrent requests");
+        return Err(AppError::TooManyRequests);
+    };
+    Ok(next.run(req).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, send};
+    use axum::{body::Body, http::StatusCode, routing::get, Router};
+    use std::sync::Arc;
+    use tokio::sync::Semaphore;
+
+    fn request(forwarded_for: &[&str]) -> Request<Body> {
+        let mut req = Request::get("/").body(Body::empty()).unwrap();
+        for hops in forwarded_for {
+            req.headers_mut()
+                .append("x-forwarded-for", hops.parse().unwrap());
+        }
+        let peer = SocketAddr::from(([10, 0, 0, 1], 4000));
+        req.extensions_mut().insert(ConnectInfo(peer));
+        req
+    }
+
+    fn ip(ip: &str) -> Option<IpAddr> {
+        Some(ip.parse().unwrap())
+    }
+
+    #[test]
+    fn client_ip_is_the_peer_unless_proxies_are_trusted() {
+        let req = request(&["1.1.1.1, 2.2.2.2"]);
+
+        assert_eq!(client_ip(&req, false, 1), ip("10.0.0.1"));
+        assert_eq!(client_ip(&req, true, 1), ip("2.2.2.2"));
+    }
+
+    #[test]
+    fn forwarded_for_skips_the_trusted_proxies_from_the_right() {
+        let req = request(&["6.6.6.6, 1.1.1.1", "2.2.2.2"]);
+
+        assert_eq!(client_ip(&req, true, 1), ip("2.2.2.2"));
+        assert_eq!(client_ip(&req, true, 2), ip("1.1.1.1"));
+        assert_eq!(client_ip(&req, true, 3), ip("6.6.6.6"));
+    }
+
+    #[test]
+    fn forwarded_for_with_fewer_hops_than_proxies_is_ignored() {
+        let req = request(&["1.1.1.1"]);
+        assert_eq!(client_ip(&req, true, 2), ip("10.0.0.1"));
+
+        let req = request(&["not-an-ip"]);
+        assert_eq!(client_ip(&req, true, 1), ip("10.0.0.1"));
+    }
+
+    #[test]
+    fn limiter_releases_on_drop() {
+        let limiter = ClientLimiter::default();
+        let client = "1.1.1.1".parse().unwrap();
+
+        let first = limiter.acquire(client, 1);
+        assert!(first.is_some());
+        assert!(limiter.acquire(client, 1).is_none());
+        assert!(limiter.acquire("2.2.2.2".parse().unwrap(), 1).is_some());
+        drop(first);
+        assert!(limiter.acquire(client, 1).is_some());
+        assert!(limiter.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn concurrent_requests_past_the_limit_are_rejected() {
+        let test = test_support::state(&[("MAX_CONCURRENT_PER_CLIENT", "2")]);
+        // the requests are stuck in the handler until there's a permit for them
+        let release = Arc::new(Semaphore::new(0));
+        let waiting = release.clone();
+        let handler = move || async move { waiting.acquire().await.unwrap().forget() };
+        let app = Router::new()
+            .route("/", get(handler))
+            .layer(axum::middleware::from_fn_with_state(
+                test.state.clone(),
+                limit_per_client,
+            ))
+            .with_state(test.state.clone());
+        let in_flight = || -> usize {
+            let in_flight = test.state.client_limiter.in_flight.lock().unwrap();
+            in_flight.values().sum()
+        };
+
+        let requests = (0..2)
+            .map(|_| tokio::spawn(send(app.clone(), request(&[]))))
+            .collect::<Vec<_>>();
+        while in_flight() < 2 {
+            tokio::task::yield_now().await;
+        }
+
+        let rejected = send(app.clone(), request(&[])).await;
+        assert_eq!(rejected.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        release.add_permits(2);
+        for response in requests {
+            assert_eq!(response.await.unwrap().status(), StatusCode::OK);
+        }
+        assert_eq!(in_flight(), 0);
+    }
+}
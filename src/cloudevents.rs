@@ -0,0 +1,151 @@
+use crate::{datadog::JsonVisitor, telemetry};
+use serde_json::{json, Map};
+use std::fmt;
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::{
+    fmt::{format::Writer, FmtContext, FormatEvent, FormatFields},
+    registry::LookupSpan,
+};
+
+/// `type` of every event.
+const EVENT_TYPE: &str = "tracing.event";
+
+/// Writes each event as a JSON [CloudEvent](https://cloudevents.io) (spec 1.0), with the
+/// event fields, level, target, span names and `log_schema_version` as its `data`.
+pub struct CloudEventFormat {
+    source: String,
+    location: bool,
+    target_prefix: Option<String>,
+}
+
+impl CloudEventFormat {
+    /// `source` identifies the producer, usually the service name.
+    pub fn new(source: impl Into<String>) -> Self {
+        Self {
+            source: source.into(),
+            location: false,
+            target_prefix: None,
+        }
+    }
+
+    /// Adds the `file` and `line` of the caller to the `data`.
+    pub fn with_location(mut self, location: bool) -> Self {
+        self.location = location;
+        self
+    }
+
+    /// Prepends `<prefix>::` to the targets.
+    pub fn with_target_prefix(mut self, target_prefix: Option<&str>) -> Self {
+        self.target_prefix = target_prefix.map(str::to_string);
+        self
+    }
+}
+
+impl<S, N> FormatEvent<S, N> for CloudEventFormat
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> fmt::Result {
+        let metadata = event.metadata();
+        let mut data = Map::new();
+        event.record(&mut JsonVisitor(&mut data));
+        data.insert("level".into(), json!(metadata.level().as_str()));
+        let target = match &self.target_prefix {
+            Some(prefix) => format!("{prefix}::{}", metadata.target()),
+            None => metadata.target().to_string(),
+        };
+        data.insert("target".into(), json!(target));
+        data.insert(
+            "log_schema_version".into(),
+            json!(telemetry::LOG_SCHEMA_VERSION),
+        );
+        if self.location {
+            data.insert("file".into(), json!(metadata.file()));
+            data.insert("line".into(), json!(metadata.line()));
+        }
+        if let Some(scope) = ctx.event_scope() {
+            let spans = scope
+                .from_root()
+                .map(|span| span.name())
+                .collect::<Vec<_>>();
+            data.insert("spans".into(), json!(spans));
+        }
+
+        let time = OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .map_err(|_| fmt::Error)?;
+        let cloud_event = json!({
+            "specversion": "1.0",
+            "type": EVENT_TYPE,
+            "source": self.source,
+            "id": format!("{:032x}", rand::random::<u128>()),
+            "time": time,
+            "datacontenttype": "application/json",
+            "data": data,
+        });
+        writeln!(writer, "{cloud_event}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Logs;
+    use serde_json::Value;
+    use tracing_subscriber::{fmt, prelude::*, Registry};
+
+    fn cloud_events(f: impl FnOnce()) -> Vec<Value> {
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let layer = fmt::layer()
+            .with_writer(move || writer.clone())
+            .event_format(CloudEventFormat::new("test-service"));
+        tracing::subscriber::with_default(Registry::default().with(layer), f);
+        logs.events()
+    }
+
+    #[test]
+    fn events_are_valid_cloud_events() {
+        let events = cloud_events(|| {
+            tracing::info_span!("request").in_scope(|| {
+                tracing::warn!(status = 503, ready = false, "not ready");
+            });
+        });
+
+        let event = &events[0];
+        assert_eq!(event["specversion"], "1.0");
+        assert_eq!(event["type"], EVENT_TYPE);
+        assert_eq!(event["source"], "test-service");
+        assert_eq!(event["datacontenttype"], "application/json");
+        assert_eq!(event["id"].as_str().map(str::len), Some(32));
+        let time = event["time"].as_str().expect("no time");
+        assert!(OffsetDateTime::parse(time, &Rfc3339).is_ok(), "{time}");
+
+        let data = &event["data"];
+        assert_eq!(data["message"], "not ready");
+        assert_eq!(data["status"], 503);
+        assert_eq!(data["ready"], false);
+        assert_eq!(data["level"], "WARN");
+        assert_eq!(data["target"], module_path!());
+        assert_eq!(data["spans"], json!(["request"]));
+        assert_eq!(data["log_schema_version"], telemetry::LOG_SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn every_event_has_its_own_id() {
+        let events = cloud_events(|| {
+            tracing::info!("one");
+            tracing::info!("two");
+        });
+
+        assert_eq!(events.len(), 2);
+        assert_ne!(events[0]["id"], events[1]["id"]);
+    }
+}
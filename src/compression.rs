@@ -0,0 +1,112 @@
+use axum::http::{header, Extensions, HeaderMap, StatusCode, Version};
+use tower_http::compression::{
+    predicate::{Predicate, SizeAbove},
+    CompressionLayer,
+};
+
+/// Content types worth compressing. Anything else (images, archives...) is already compressed
+/// or too small to matter.
+const COMPRESSIBLE_CONTENT_TYPES: &[&str] = &[
+    "text/",
+    "application/json",
+    "application/problem+json",
+    "application/javascript",
+    "application/xml",
+    "image/svg+xml",
+];
+
+/// Compresses responses of at least `min_bytes` with a compressible content type.
+pub fn layer(min_bytes: u16) -> CompressionLayer<impl Predicate> {
+    CompressionLayer::new().compress_when(
+        SizeAbove::new(min_bytes)
+            .and(is_compressible as fn(StatusCode, Version, &HeaderMap, &Extensions) -> bool),
+    )
+}
+
+fn is_compressible(_: StatusCode, _: Version, headers: &HeaderMap, _: &Extensions) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|content_type| {
+            COMPRESSIBLE_CONTENT_TYPES
+                .iter()
+                .any(|compressible| content_type.starts_with(compressible))
+        })
+        .unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::send;
+    use axum::{
+        body::Body,
+        http::{HeaderValue, Request},
+        routing::get,
+        Router,
+    };
+
+    fn router(min_bytes: u16) -> Router {
+        Router::new()
+            .route("/big", get(|| async { "x".repeat(2048) }))
+            .route("/small", get(|| async { "x".repeat(16) }))
+            .route(
+                "/binary",
+                get(|| async { ([(header::CONTENT_TYPE, "image/png")], vec![0; 2048]) }),
+            )
+            .layer(layer(min_bytes))
+    }
+
+    async fn content_encoding(router: Router, uri: &str) -> Option<HeaderValue> {
+        let req = Request::get(uri)
+            .header(header::ACCEPT_ENCODING, "gzip")
+            .body(Body::empty())
+            .unwrap();
+        send(router, req)
+            .await
+            .headers()
+            .get(header::CONTENT_ENCODING)
+            .cloned()
+    }
+
+    #[tokio::test]
+    async fn only_responses_above_the_threshold_are_compressed() {
+        assert_eq!(
+            content_encoding(router(1024), "/big").await.unwrap(),
+            "gzip"
+        );
+        assert_eq!(content_encoding(router(1024), "/small").await, None);
+        assert_eq!(content_encoding(router(0), "/small").await.unwrap(), "gzip");
+    }
+
+    #[tokio::test]
+    async fn already_compressed_content_types_are_not_compressed() {
+        assert_eq!(content_encoding(router(0), "/binary").await, None);
+    }
+
+    #[test]
+    fn compressible_content_types() {
+        let compressible = |content_type: &'static str| {
+            let headers = HeaderMap::from_iter([(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(content_type),
+            )]);
+            is_compressible(
+                StatusCode::OK,
+                Version::HTTP_11,
+                &headers,
+                &Extensions::new(),
+            )
+        };
+
+        assert!(compressible("text/plain; charset=utf-8"));
+        assert!(compressible("application/problem+json"));
+        assert!(!compressible("image/png"));
+        assert!(!is_compressible(
+            StatusCode::OK,
+            Version::HTTP_11,
+            &HeaderMap::new(),
+            &Extensions::new()
+        ));
+    }
+}
@@ -0,0 +1,1178 @@
+use crate::{
+    build_info,
+    datadog::{self, Compression, Region},
+    health::{HealthBody, ReadinessCheck},
+    middleware::RequiredHeader,
+    query,
+    request_id::RequestIdFormat,
+    secrets::{SecretProvider, Secrets},
+    service::StartupLogStyle,
+    telemetry::{self, LogFormat},
+};
+use anyhow::Context;
+use std::{fmt, str::FromStr};
+
+const VERSION: &str = "version:0.1.0";
+/// `DD_SERVICE` unless set, so each fork of the template gets its own.
+const DEFAULT_SERVICE: &str = env!("CARGO_PKG_NAME");
+/// hyper panics with a smaller read buffer.
+const MIN_HEADER_BYTES: usize = 8192;
+
+/// Settings resolved from the Shuttle secret store.
+///
+/// `Debug` masks the secrets, so the config can be logged.
+#[derive(Clone, PartialEq)]
+pub struct Config {
+    /// Datadog API Key. Can't be hot-reloaded: the Datadog layer is created once at startup.
+    pub dd_api_key: String,
+    /// `DD_TAGS` as provided.
+    pub dd_tags: Option<String>,
+    /// `DD_TAGS_ENV` as provided, overrides the `DD_TAGS` with the same key.
+    pub dd_tags_env: Option<String>,
+    /// `DD_TAGS_EXTRA` as provided, overrides the other tags with the same key.
+    pub dd_tags_extra: Option<String>,
+    /// Appends our `version:` tag. Disable it when using Datadog's unified tagging.
+    pub dd_append_version: bool,
+    /// Datadog tags, including the version tag. Can't be hot-reloaded either.
+    pub tags: String,
+    /// Seconds between the `dd_queue` logs with the Datadog queue depth, `0` disables them.
+    /// Requires a restart.
+    pub dd_queue_report_interval_s: u64,
+    /// Spans/targets whose events are shipped to Datadog. Empty means all. Requires a restart.
+    pub dd_span_allowlist: Vec<String>,
+    /// Spans/targets whose events are never shipped to Datadog. Requires a restart.
+    pub dd_span_denylist: Vec<String>,
+    /// Datadog region (`US1`, `US3`, `US5`, `US1FED` or `EU`). Requires a restart.
+    pub region: Region,
+    /// Base URL of the Datadog API used to validate the API key (`DD_API_URL`), defaulting to
+    /// the one of the region.
+    pub dd_api_url: String,
+    /// Environment the service is deployed to (`DD_ENV`). Requires a restart.
+    pub dd_env: Option<String>,
+    /// Adds the `X-Region` and `X-Env` headers to the responses. Requires a restart.
+    pub expose_deployment_headers: bool,
+    /// `EnvFilter` directives. Hot-reloadable.
+    pub log_level: String,
+    /// Token required to access the `/admin` endpoints. They're disabled when it's not set.
+    pub admin_token: Option<String>,
+    /// Enables the debugging endpoints under `/admin`. Requires a restart.
+    pub debug_endpoints: bool,
+    /// Number of requests kept by the debug capture. `0` disables it. Requires a restart.
+    pub capture_requests: usize,
+    /// Lowercased request/response headers recorded by the debug capture.
+    pub capture_headers: Vec<String>,
+    /// Trust the `X-Forwarded-*` headers set by the proxy in front of us.
+    pub trust_proxy: bool,
+    /// Maximum number of in-flight requests per client IP. `0` means unlimited.
+    /// Enabling/disabling it requires a restart.
+    pub max_concurrent_per_client: usize,
+    /// Responses smaller than this aren't compressed. Requires a restart.
+    pub compress_min_bytes: u16,
+    /// Body of `/health`.
+    pub health_body: HealthBody,
+    /// Prints the settings as `export` lines at startup.
+    pub dump_env: bool,
+    /// Stops the server after this many seconds. Requires a restart.
+    pub max_runtime_s: Option<u64>,
+    /// Fraction of `hello_world` spans that are created. Requires a restart.
+    pub hello_span_sample: f64,
+    /// Proxies in front of us appending to `X-Forwarded-For`, see `client::client_ip`.
+    pub trusted_proxies: usize,
+    /// Serves HTTP/2 over cleartext (h2c) instead of HTTP/1.1. Requires a restart.
+    pub http2_cleartext: bool,
+    /// Warns when more events than this are shipped to Datadog in a minute. Requires a restart.
+    pub log_budget_per_min: Option<u64>,
+    /// Body of `/robots.txt`.
+    pub robots_txt: Option<String>,
+    /// Body of `/.well-known/security.txt`.
+    pub security_txt: Option<String>,
+    /// Requests with bigger heads (request line and headers) get a `431`. Requires a restart.
+    pub max_header_bytes: Option<usize>,
+    /// Request headers echoed back as `X-Echo-<header>`. Enabling or disabling it requires a restart.
+    pub debug_echo_headers: Vec<String>,
+    /// Seconds between `heartbeat` logs, `0` disables them. Requires a restart.
+    pub heartbeat_interval_s: u64,
+    /// Errors are returned as RFC 7807 problem details instead of `{"error": ...}`.
+    pub problem_json: bool,
+    /// Spans created beyond this many in a request are dropped. Requires a restart.
+    pub max_spans_per_request: Option<usize>,
+    /// Prefix of the logged targets (`<prefix>::<target>`). Requires a restart.
+    pub logger_prefix: Option<String>,
+    /// Requests with longer URIs get a `414` (`0` disables it). Enabling or disabling it requires a restart.
+    pub max_uri_len: usize,
+    /// Compression of the payloads sent to Datadog (`none`, `gzip` or `deflate`). Requires a
+    /// restart.
+    pub dd_compression: Compression,
+    /// Adds the `file` and `line` of the caller to the stdout and Datadog logs. Requires a
+    /// restart.
+    pub log_location: bool,
+    /// Routes that aren't registered at all. Requires a restart.
+    pub disabled_routes: Vec<String>,
+    /// Datadog service name, normalized before use. Requires a restart.
+    pub dd_service: String,
+    /// Shape of the stdout logs (`json` or `cloudevents`). Requires a restart.
+    pub log_format: LogFormat,
+    /// Extra tags for the `ERROR` events shipped to Datadog. Requires a restart.
+    pub dd_error_tags: Option<String>,
+    /// Logs the query string of the requests, redacted. Requires a restart.
+    pub log_query: bool,
+    /// Query parameters whose values are masked in the logs.
+    pub redact_query_params: Vec<String>,
+    /// Adds the pod name as the `pod_name` tag and `host` field. Requires a restart.
+    pub tag_pod_name: bool,
+    /// From `POD_NAME` or `HOSTNAME`, only when `tag_pod_name` is enabled.
+    pub pod_name: Option<String>,
+    /// Dependencies that must be up for `/ready` to succeed.
+    pub readiness_checks: Vec<ReadinessCheck>,
+    /// Fraction of the `/health` and `/ready` requests whose completion is logged.
+    pub health_log_sample: f64,
+    /// Key of the `X-Response-Signature` HMAC. Enabling or disabling it requires a restart.
+    pub response_signing_key: Option<String>,
+    /// Startups slower than this log a `slow startup` warning. Requires a restart.
+    pub startup_slo_ms: Option<u64>,
+    /// Request header whose value is added as `tenant_id` to every log of the request.
+    pub tenant_header: Option<String>,
+    /// How the request ids are generated (`uuid`, `ulid` or `nano`).
+    pub request_id_format: RequestIdFormat,
+    /// Serves `/admin` on its own listener bound to `127.0.0.1:<port>` instead of the main one.
+    pub admin_port: Option<u16>,
+    /// Flushes the Datadog logs on `SIGUSR1` (Unix only). Requires a restart.
+    pub sigusr1_flush: bool,
+    /// Header (`name=value` or just `name`) every request but the health checks must have.
+    pub require_header: Option<RequiredHeader>,
+    /// `verbose` logs each startup step, `summary` a single `startup` event.
+    pub startup_log_style: StartupLogStyle,
+    /// Timeout of the `GET`, `HEAD` and `OPTIONS` requests.
+    pub read_timeout_ms: Option<u64>,
+    /// Timeout of the requests with any other method.
+    pub write_timeout_ms: Option<u64>,
+    /// Canary deployment: the logs are tagged `canary:true` and responses get `X-Canary: true`.
+    pub canary: bool,
+    /// Key the admin requests must also be signed with, see `signature::verify_admin_request`.
+    pub admin_hmac_key: Option<String>,
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("dd_api_key", &mask(&self.dd_api_key))
+            .field("dd_tags", &self.dd_tags)
+            .field("dd_tags_env", &self.dd_tags_env)
+            .field("dd_tags_extra", &self.dd_tags_extra)
+            .field("dd_append_version", &self.dd_append_version)
+            .field("tags", &self.tags)
+            .field(
+                "dd_queue_report_interval_s",
+                &self.dd_queue_report_interval_s,
+            )
+            .field("dd_span_allowlist", &self.dd_span_allowlist)
+            .field("dd_span_denylist", &self.dd_span_denylist)
+            .field("region", &self.region)
+            .field("dd_api_url", &self.dd_api_url)
+            .field("dd_env", &self.dd_env)
+            .field("expose_deployment_headers", &self.expose_deployment_headers)
+            .field("log_level", &self.log_level)
+            .field("admin_token", &self.admin_token.as_deref().map(mask))
+            .field("debug_endpoints", &self.debug_endpoints)
+            .field("capture_requests", &self.capture_requests)
+            .field("capture_headers", &self.capture_headers)
+            .field("trust_proxy", &self.trust_proxy)
+            .field("max_concurrent_per_client", &self.max_concurrent_per_client)
+            .field("compress_min_bytes", &self.compress_min_bytes)
+            .field("health_body", &self.health_body)
+            .field("dump_env", &self.dump_env)
+            .field("max_runtime_s", &self.max_runtime_s)
+            .field("hello_span_sample", &self.hello_span_sample)
+            .field("trusted_proxies", &self.trusted_proxies)
+            .field("http2_cleartext", &self.http2_cleartext)
+            .field("log_budget_per_min", &self.log_budget_per_min)
+            .field("robots_txt", &self.robots_txt)
+            .field("security_txt", &self.security_txt)
+            .field("max_header_bytes", &self.max_header_bytes)
+            .field("debug_echo_headers", &self.debug_echo_headers)
+            .field("heartbeat_interval_s", &self.heartbeat_interval_s)
+            .field("problem_json", &self.problem_json)
+            .field("max_spans_per_request", &self.max_spans_per_request)
+            .field("logger_prefix", &self.logger_prefix)
+            .field("max_uri_len", &self.max_uri_len)
+            .field("dd_compression", &self.dd_compression)
+            .field("log_location", &self.log_location)
+            .field("disabled_routes", &self.disabled_routes)
+            .field("dd_service", &self.dd_service)
+            .field("log_format", &self.log_format)
+            .field("dd_error_tags", &self.dd_error_tags)
+            .field("log_query", &self.log_query)
+            .field("redact_query_params", &self.redact_query_params)
+            .field("tag_pod_name", &self.tag_pod_name)
+            .field("pod_name", &self.pod_name)
+            .field("readiness_checks", &self.readiness_checks)
+            .field("health_log_sample", &self.health_log_sample)
+            .field(
+                "response_signing_key",
+                &self.response_signing_key.as_deref().map(mask),
+            )
+            .field("startup_slo_ms", &self.startup_slo_ms)
+            .field("tenant_header", &self.tenant_header)
+            .field("request_id_format", &self.request_id_format)
+            .field("admin_port", &self.admin_port)
+            .field("sigusr1_flush", &self.sigusr1_flush)
+            .field(
+                "require_header",
+                &self.require_header.as_ref().map(masked_required_header),
+            )
+            .field("startup_log_style", &self.startup_log_style)
+            .field("read_timeout_ms", &self.read_timeout_ms)
+            .field("write_timeout_ms", &self.write_timeout_ms)
+            .field("canary", &self.canary)
+            .field("admin_hmac_key", &self.admin_hmac_key.as_deref().map(mask))
+            .finish()
+    }
+}
+
+impl Config {
+    pub fn from_secrets(secret_store: &Secrets) -> anyhow::Result<Self> {
+        // getting the Datadog Key from the secrets
+        let dd_api_key = require_secret(secret_store, "DD_API_KEY")?;
+
+        // getting the Datadog tags from the secrets
+        let dd_tags = secret(secret_store, "DD_TAGS");
+        let dd_tags_env = secret(secret_store, "DD_TAGS_ENV");
+        let dd_tags_extra = secret(secret_store, "DD_TAGS_EXTRA");
+        let dd_append_version = flag_secret(secret_store, "DD_APPEND_VERSION")?.unwrap_or(true);
+        // in Kubernetes HOSTNAME is the pod name, POD_NAME (e.g. from the downward API) wins
+        let tag_pod_name = flag_secret(secret_store, "TAG_POD_NAME")?.unwrap_or(false);
+        let pod_name = tag_pod_name
+            .then(|| env_var("POD_NAME").or_else(|| env_var("HOSTNAME")))
+            .flatten();
+        let dd_env = secret(secret_store, "DD_ENV");
+        let canary = flag_secret(secret_store, "CANARY")?.unwrap_or(false);
+        let dd_service =
+            secret(secret_store, "DD_SERVICE").unwrap_or_else(|| DEFAULT_SERVICE.to_string());
+        let tags = compose_tags(
+            &[
+                dd_tags.as_deref(),
+                dd_tags_env.as_deref(),
+                dd_tags_extra.as_deref(),
+                canary.then_some("canary:true"),
+            ],
+            dd_append_version,
+            &telemetry::normalize_service_name(&dd_service),
+            pod_name.as_deref(),
+            dd_env.as_deref(),
+        );
+
+        let dd_queue_report_interval_s =
+            parse_secret(secret_store, "DD_QUEUE_REPORT_INTERVAL_S")?.unwrap_or(0);
+
+        // which spans are shipped to Datadog (local logs keep everything)
+        let dd_span_allowlist = list_secret(secret_store, "DD_SPAN_ALLOWLIST").unwrap_or_default();
+        let dd_span_denylist = list_secret(secret_store, "DD_SPAN_DENYLIST").unwrap_or_default();
+
+        // getting the Datadog region from the secrets (US1 is the default)
+        let region = secret(secret_store, "DD_REGION")
+            .map(|region| datadog::parse_region(&region))
+            .transpose()?
+            .unwrap_or(Region::US1);
+        let dd_api_url = secret(secret_store, "DD_API_URL")
+            .map(|url| url.trim_end_matches('/').to_string())
+            .unwrap_or_else(|| datadog::api_url(region));
+
+        let expose_deployment_headers =
+            flag_secret(secret_store, "EXPOSE_DEPLOYMENT_HEADERS")?.unwrap_or(false);
+
+        // getting the log level from the secrets
+        let log_level = secret(secret_store, "LOG_LEVEL").unwrap_or("INFO".to_string());
+        // fail early instead of when the filter is built or reloaded
+        tracing_subscriber::EnvFilter::try_new(&log_level)
+            .with_context(|| format!("invalid LOG_LEVEL: {log_level}"))?;
+
+        let admin_token = secret(secret_store, "ADMIN_TOKEN");
+        let debug_endpoints = flag_secret(secret_store, "DEBUG_ENDPOINTS")?.unwrap_or(false);
+
+        // debug capture of requests/responses, exposed in `/admin/captures`
+        let capture_requests = parse_secret(secret_store, "CAPTURE_REQUESTS")?.unwrap_or(0);
+        let capture_headers = list_secret(secret_store, "CAPTURE_HEADERS")
+            .unwrap_or_else(|| vec!["content-type".into(), "user-agent".into()]);
+
+        let trust_proxy = flag_secret(secret_store, "TRUST_PROXY")?.unwrap_or(false);
+        let max_concurrent_per_client =
+            parse_secret(secret_store, "MAX_CONCURRENT_PER_CLIENT")?.unwrap_or(0);
+
+        let compress_min_bytes = parse_secret(secret_store, "COMPRESS_MIN_BYTES")?.unwrap_or(1024);
+
+        let health_body = secret(secret_store, "HEALTH_BODY")
+            .map(|body| HealthBody::parse(&body))
+            .transpose()?
+            .unwrap_or(HealthBody::Json);
+
+        let dump_env = flag_secret(secret_store, "DUMP_ENV")?.unwrap_or(false);
+
+        let max_runtime_s = parse_secret(secret_store, "MAX_RUNTIME_S")?.filter(|secs| *secs > 0);
+
+        let hello_span_sample = parse_secret(secret_store, "HELLO_SPAN_SAMPLE")?.unwrap_or(1.0);
+        if !(0.0..=1.0).contains(&hello_span_sample) {
+            anyhow::bail!("HELLO_SPAN_SAMPLE must be between 0 and 1: {hello_span_sample}");
+        }
+
+        let trusted_proxies = parse_secret(secret_store, "TRUSTED_PROXIES")?.unwrap_or(1);
+        if trusted_proxies == 0 {
+            anyhow::bail!("TRUSTED_PROXIES must be at least 1");
+        }
+        let http2_cleartext = flag_secret(secret_store, "HTTP2_CLEARTEXT")?.unwrap_or(false);
+
+        let log_budget_per_min =
+            parse_secret(secret_store, "LOG_BUDGET_PER_MIN")?.filter(|budget| *budget > 0);
+
+        // crawlers and scanners ask for these, better to answer than to 404
+        let robots_txt = secret(secret_store, "ROBOTS_TXT");
+        let security_txt = secret(secret_store, "SECURITY_TXT");
+
+        // hyper answers `431` on its own when the head doesn't fit its read buffer
+        let max_header_bytes = parse_secret(secret_store, "MAX_HEADER_BYTES")?;
+        if let Some(bytes) = max_header_bytes.filter(|bytes| *bytes < MIN_HEADER_BYTES) {
+            anyhow::bail!("MAX_HEADER_BYTES must be at least {MIN_HEADER_BYTES}: {bytes}");
+        }
+
+        // debugging aid for proxies, sensitive headers are never echoed
+        let debug_echo_headers =
+            list_secret(secret_store, "DEBUG_ECHO_HEADERS").unwrap_or_default();
+
+        let heartbeat_interval_s = parse_secret(secret_store, "HEARTBEAT_INTERVAL_S")?.unwrap_or(0);
+
+        let problem_json = flag_secret(secret_store, "PROBLEM_JSON")?.unwrap_or(false);
+
+        let max_spans_per_request =
+            parse_secret(secret_store, "MAX_SPANS_PER_REQUEST")?.filter(|max| *max > 0);
+
+        let logger_prefix = secret(secret_store, "LOGGER_PREFIX");
+
+        let max_uri_len = parse_secret(secret_store, "MAX_URI_LEN")?.unwrap_or(0);
+
+        let dd_compression = secret(secret_store, "DD_COMPRESSION")
+            .map(|compression| Compression::parse(&compression))
+            .transpose()?
+            .unwrap_or(Compression::Gzip);
+
+        let log_location = flag_secret(secret_store, "LOG_LOCATION")?.unwrap_or(false);
+
+        let disabled_routes = list_secret(secret_store, "DISABLED_ROUTES").unwrap_or_default();
+
+        let log_format = secret(secret_store, "LOG_FORMAT")
+            .map(|format| LogFormat::parse(&format))
+            .transpose()?
+            .unwrap_or(LogFormat::Json);
+
+        // lets error logs be routed to their own index or pipeline
+        let dd_error_tags = secret(secret_store, "DD_ERROR_TAGS");
+
+        // query strings are only logged on demand, and with the sensitive values masked
+        let log_query = flag_secret(secret_store, "LOG_QUERY")?.unwrap_or(false);
+        let redact_query_params =
+            list_secret(secret_store, "REDACT_QUERY_PARAMS").unwrap_or_else(|| {
+                query::DEFAULT_REDACTED_PARAMS
+                    .iter()
+                    .map(|param| param.to_string())
+                    .collect()
+            });
+
+        let readiness_checks = list_secret(secret_store, "READINESS_CHECKS")
+            .unwrap_or_default()
+            .iter()
+            .map(|check| ReadinessCheck::parse(check))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let health_log_sample = parse_secret(secret_store, "HEALTH_LOG_SAMPLE")?.unwrap_or(1.0);
+        if !(0.0..=1.0).contains(&health_log_sample) {
+            anyhow::bail!("HEALTH_LOG_SAMPLE must be between 0 and 1: {health_log_sample}");
+        }
+
+        let response_signing_key = secret(secret_store, "RESPONSE_SIGNING_KEY");
+
+        let startup_slo_ms = parse_secret(secret_store, "STARTUP_SLO_MS")?;
+
+        let tenant_header =
+            secret(secret_store, "TENANT_HEADER").map(|header| header.trim().to_lowercase());
+
+        let request_id_format = secret(secret_store, "REQUEST_ID_FORMAT")
+            .map(|format| RequestIdFormat::parse(&format))
+            .transpose()?
+            .unwrap_or(RequestIdFormat::Uuid);
+
+        let admin_port = parse_secret(secret_store, "ADMIN_PORT")?;
+
+        let sigusr1_flush = flag_secret(secret_store, "SIGUSR1_FLUSH")?.unwrap_or(false);
+
+        let require_header = secret(secret_store, "REQUIRE_HEADER")
+            .map(|require_header| RequiredHeader::parse(&require_header))
+            .transpose()?;
+
+        let startup_log_style = secret(secret_store, "STARTUP_LOG_STYLE")
+            .map(|style| StartupLogStyle::parse(&style))
+            .transpose()?
+            .unwrap_or(StartupLogStyle::Verbose);
+
+        let read_timeout_ms = parse_secret(secret_store, "READ_TIMEOUT_MS")?.filter(|ms| *ms > 0);
+
+        let write_timeout_ms = parse_secret(secret_store, "WRITE_TIMEOUT_MS")?.filter(|ms| *ms > 0);
+
+        let admin_hmac_key = secret(secret_store, "ADMIN_HMAC_KEY");
+
+        Ok(Self {
+            dd_api_key,
+            dd_tags,
+            dd_tags_env,
+            dd_tags_extra,
+            dd_append_version,
+            tags,
+            dd_queue_report_interval_s,
+            dd_span_allowlist,
+            dd_span_denylist,
+            region,
+            dd_api_url,
+            dd_env,
+            expose_deployment_headers,
+            log_level,
+            admin_token,
+            debug_endpoints,
+            capture_requests,
+            capture_headers,
+            trust_proxy,
+            max_concurrent_per_client,
+            compress_min_bytes,
+            health_body,
+            dump_env,
+            max_runtime_s,
+            hello_span_sample,
+            trusted_proxies,
+            http2_cleartext,
+            log_budget_per_min,
+            robots_txt,
+            security_txt,
+            max_header_bytes,
+            debug_echo_headers,
+            heartbeat_interval_s,
+            problem_json,
+            max_spans_per_request,
+            logger_prefix,
+            max_uri_len,
+            dd_compression,
+            log_location,
+            disabled_routes,
+            dd_service,
+            log_format,
+            dd_error_tags,
+            log_query,
+            redact_query_params,
+            tag_pod_name,
+            pod_name,
+            readiness_checks,
+            health_log_sample,
+            response_signing_key,
+            startup_slo_ms,
+            tenant_header,
+            request_id_format,
+            admin_port,
+            sigusr1_flush,
+            require_header,
+            startup_log_style,
+            read_timeout_ms,
+            write_timeout_ms,
+            canary,
+            admin_hmac_key,
+        })
+    }
+
+    /// Applies the hot-reloadable settings of `new`.
+    ///
+    /// Returns the names of the settings that changed but only take effect after a restart.
+    pub fn reload_from(&mut self, new: Self) -> Vec<&'static str> {
+        let mut requires_restart = vec![];
+        if self.dd_api_key != new.dd_api_key {
+            requires_restart.push("DD_API_KEY");
+        }
+        if self.dd_tags != new.dd_tags {
+            requires_restart.push("DD_TAGS");
+        }
+        if self.dd_tags_env != new.dd_tags_env {
+            requires_restart.push("DD_TAGS_ENV");
+        }
+        if self.dd_tags_extra != new.dd_tags_extra {
+            requires_restart.push("DD_TAGS_EXTRA");
+        }
+        if self.dd_append_version != new.dd_append_version {
+            requires_restart.push("DD_APPEND_VERSION");
+        }
+        if self.dd_queue_report_interval_s != new.dd_queue_report_interval_s {
+            requires_restart.push("DD_QUEUE_REPORT_INTERVAL_S");
+        }
+        if self.dd_span_allowlist != new.dd_span_allowlist {
+            requires_restart.push("DD_SPAN_ALLOWLIST");
+        }
+        if self.dd_span_denylist != new.dd_span_denylist {
+            requires_restart.push("DD_SPAN_DENYLIST");
+        }
+        if self.region != new.region {
+            requires_restart.push("DD_REGION");
+        }
+        if self.dd_env != new.dd_env {
+            requires_restart.push("DD_ENV");
+        }
+        if self.expose_deployment_headers != new.expose_deployment_headers {
+            requires_restart.push("EXPOSE_DEPLOYMENT_HEADERS");
+        }
+        if self.debug_endpoints != new.debug_endpoints {
+            requires_restart.push("DEBUG_ENDPOINTS");
+        }
+        if self.capture_requests != new.capture_requests {
+            requires_restart.push("CAPTURE_REQUESTS");
+        }
+        if (self.max_concurrent_per_client == 0) != (new.max_concurrent_per_client == 0) {
+            requires_restart.push("MAX_CONCURRENT_PER_CLIENT");
+        } else {
+            self.max_concurrent_per_client = new.max_concurrent_per_client;
+        }
+        if self.compress_min_bytes != new.compress_min_bytes {
+            requires_restart.push("COMPRESS_MIN_BYTES");
+        }
+        if self.max_runtime_s != new.max_runtime_s {
+            requires_restart.push("MAX_RUNTIME_S");
+        }
+        if self.hello_span_sample != new.hello_span_sample {
+            requires_restart.push("HELLO_SPAN_SAMPLE");
+        }
+        if self.http2_cleartext != new.http2_cleartext {
+            requires_restart.push("HTTP2_CLEARTEXT");
+        }
+        if self.log_budget_per_min != new.log_budget_per_min {
+            requires_restart.push("LOG_BUDGET_PER_MIN");
+        }
+        if self.max_header_bytes != new.max_header_bytes {
+            requires_restart.push("MAX_HEADER_BYTES");
+        }
+        if self.debug_echo_headers.is_empty() != new.debug_echo_headers.is_empty() {
+            requires_restart.push("DEBUG_ECHO_HEADERS");
+        }
+        if self.heartbeat_interval_s != new.heartbeat_interval_s {
+            requires_restart.push("HEARTBEAT_INTERVAL_S");
+        }
+        if self.max_spans_per_request != new.max_spans_per_request {
+            requires_restart.push("MAX_SPANS_PER_REQUEST");
+        }
+        if self.logger_prefix != new.logger_prefix {
+            requires_restart.push("LOGGER_PREFIX");
+        }
+        if self.dd_compression != new.dd_compression {
+            requires_restart.push("DD_COMPRESSION");
+        }
+        if (self.max_uri_len == 0) != (new.max_uri_len == 0) {
+            requires_restart.push("MAX_URI_LEN");
+        } else {
+            self.max_uri_len = new.max_uri_len;
+        }
+        if self.log_location != new.log_location {
+            requires_restart.push("LOG_LOCATION");
+        }
+        if self.disabled_routes != new.disabled_routes {
+            requires_restart.push("DISABLED_ROUTES");
+        }
+        if self.dd_service != new.dd_service {
+            requires_restart.push("DD_SERVICE");
+        }
+        if self.log_format != new.log_format {
+            requires_restart.push("LOG_FORMAT");
+        }
+        if self.dd_error_tags != new.dd_error_tags {
+            requires_restart.push("DD_ERROR_TAGS");
+        }
+        if self.log_query != new.log_query {
+            requires_restart.push("LOG_QUERY");
+        }
+        if self.tag_pod_name != new.tag_pod_name {
+            requires_restart.push("TAG_POD_NAME");
+        }
+        if self.response_signing_key.is_some() != new.response_signing_key.is_some() {
+            requires_restart.push("RESPONSE_SIGNING_KEY");
+        }
+        if self.startup_slo_ms != new.startup_slo_ms {
+            requires_restart.push("STARTUP_SLO_MS");
+        }
+        if self.admin_port != new.admin_port {
+            requires_restart.push("ADMIN_PORT");
+        }
+        if self.sigusr1_flush != new.sigusr1_flush {
+            requires_restart.push("SIGUSR1_FLUSH");
+        }
+        if self.startup_log_style != new.startup_log_style {
+            requires_restart.push("STARTUP_LOG_STYLE");
+        }
+        if self.canary != new.canary {
+            requires_restart.push("CANARY");
+        }
+        self.log_level = new.log_level;
+        self.dd_api_url = new.dd_api_url;
+        self.admin_token = new.admin_token;
+        self.capture_headers = new.capture_headers;
+        self.health_body = new.health_body;
+        self.trust_proxy = new.trust_proxy;
+        self.trusted_proxies = new.trusted_proxies;
+        self.robots_txt = new.robots_txt;
+        self.security_txt = new.security_txt;
+        self.debug_echo_headers = new.debug_echo_headers;
+        self.problem_json = new.problem_json;
+        self.redact_query_params = new.redact_query_params;
+        self.readiness_checks = new.readiness_checks;
+        self.health_log_sample = new.health_log_sample;
+        self.response_signing_key = new.response_signing_key;
+        self.tenant_header = new.tenant_header;
+        self.request_id_format = new.request_id_format;
+        self.require_header = new.require_header;
+        self.read_timeout_ms = new.read_timeout_ms;
+        self.write_timeout_ms = new.write_timeout_ms;
+        self.admin_hmac_key = new.admin_hmac_key;
+        requires_restart
+    }
+
+    /// The settings as `export KEY=value` lines so they can be recreated elsewhere.
+    ///
+    /// Sensitive values are masked.
+    pub fn to_env(&self) -> String {
+        let mut vars = vec![("DD_API_KEY", mask(&self.dd_api_key))];
+        if let Some(dd_tags) = &self.dd_tags {
+            vars.push(("DD_TAGS", dd_tags.clone()));
+        }
+        if let Some(dd_tags_env) = &self.dd_tags_env {
+            vars.push(("DD_TAGS_ENV", dd_tags_env.clone()));
+        }
+        if let Some(dd_tags_extra) = &self.dd_tags_extra {
+            vars.push(("DD_TAGS_EXTRA", dd_tags_extra.clone()));
+        }
+        vars.push(("DD_APPEND_VERSION", self.dd_append_version.to_string()));
+        vars.push((
+            "DD_QUEUE_REPORT_INTERVAL_S",
+            self.dd_queue_report_interval_s.to_string(),
+        ));
+        vars.push(("DD_SPAN_ALLOWLIST", self.dd_span_allowlist.join(",")));
+        vars.push(("DD_SPAN_DENYLIST", self.dd_span_denylist.join(",")));
+        vars.push(("DD_REGION", format!("{:?}", self.region)));
+        vars.push(("DD_API_URL", self.dd_api_url.clone()));
+        if let Some(dd_env) = &self.dd_env {
+            vars.push(("DD_ENV", dd_env.clone()));
+        }
+        vars.push((
+            "EXPOSE_DEPLOYMENT_HEADERS",
+            self.expose_deployment_headers.to_string(),
+        ));
+        vars.push(("LOG_LEVEL", self.log_level.clone()));
+        if let Some(admin_token) = &self.admin_token {
+            vars.push(("ADMIN_TOKEN", mask(admin_token)));
+        }
+        vars.push(("DEBUG_ENDPOINTS", self.debug_endpoints.to_string()));
+        vars.push(("CAPTURE_REQUESTS", self.capture_requests.to_string()));
+        vars.push(("CAPTURE_HEADERS", self.capture_headers.join(",")));
+        vars.push(("TRUST_PROXY", self.trust_proxy.to_string()));
+        vars.push((
+            "MAX_CONCURRENT_PER_CLIENT",
+            self.max_concurrent_per_client.to_string(),
+        ));
+        vars.push(("COMPRESS_MIN_BYTES", self.compress_min_bytes.to_string()));
+        vars.push(("HEALTH_BODY", self.health_body.as_str().to_string()));
+        if let Some(max_runtime_s) = self.max_runtime_s {
+            vars.push(("MAX_RUNTIME_S", max_runtime_s.to_string()));
+        }
+        vars.push(("HELLO_SPAN_SAMPLE", self.hello_span_sample.to_string()));
+        vars.push(("TRUSTED_PROXIES", self.trusted_proxies.to_string()));
+        vars.push(("HTTP2_CLEARTEXT", self.http2_cleartext.to_string()));
+        if let Some(log_budget_per_min) = self.log_budget_per_min {
+            vars.push(("LOG_BUDGET_PER_MIN", log_budget_per_min.to_string()));
+        }
+        if let Some(robots_txt) = &self.robots_txt {
+            vars.push(("ROBOTS_TXT", robots_txt.clone()));
+        }
+        if let Some(security_txt) = &self.security_txt {
+            vars.push(("SECURITY_TXT", security_txt.clone()));
+        }
+        if let Some(max_header_bytes) = self.max_header_bytes {
+            vars.push(("MAX_HEADER_BYTES", max_header_bytes.to_string()));
+        }
+        vars.push(("DEBUG_ECHO_HEADERS", self.debug_echo_headers.join(",")));
+        vars.push((
+            "HEARTBEAT_INTERVAL_S",
+            self.heartbeat_interval_s.to_string(),
+        ));
+        vars.push(("PROBLEM_JSON", self.problem_json.to_string()));
+        if let Some(max_spans_per_request) = self.max_spans_per_request {
+            vars.push(("MAX_SPANS_PER_REQUEST", max_spans_per_request.to_string()));
+        }
+        if let Some(logger_prefix) = &self.logger_prefix {
+            vars.push(("LOGGER_PREFIX", logger_prefix.clone()));
+        }
+        vars.push(("MAX_URI_LEN", self.max_uri_len.to_string()));
+        vars.push(("DD_COMPRESSION", self.dd_compression.as_str().to_string()));
+        vars.push(("LOG_LOCATION", self.log_location.to_string()));
+        vars.push(("DISABLED_ROUTES", self.disabled_routes.join(",")));
+        vars.push(("DD_SERVICE", self.dd_service.clone()));
+        vars.push(("LOG_FORMAT", self.log_format.as_str().to_string()));
+        if let Some(dd_error_tags) = &self.dd_error_tags {
+            vars.push(("DD_ERROR_TAGS", dd_error_tags.clone()));
+        }
+        vars.push(("LOG_QUERY", self.log_query.to_string()));
+        vars.push(("REDACT_QUERY_PARAMS", self.redact_query_params.join(",")));
+        vars.push(("TAG_POD_NAME", self.tag_pod_name.to_string()));
+        let readiness_checks = self.readiness_checks.iter().map(ReadinessCheck::as_str);
+        vars.push((
+            "READINESS_CHECKS",
+            readiness_checks.collect::<Vec<_>>().join(","),
+        ));
+        vars.push(("HEALTH_LOG_SAMPLE", self.health_log_sample.to_string()));
+        if let Some(response_signing_key) = &self.response_signing_key {
+            vars.push(("RESPONSE_SIGNING_KEY", mask(response_signing_key)));
+        }
+        if let Some(startup_slo_ms) = self.startup_slo_ms {
+            vars.push(("STARTUP_SLO_MS", startup_slo_ms.to_string()));
+        }
+        if let Some(tenant_header) = &self.tenant_header {
+            vars.push(("TENANT_HEADER", tenant_header.clone()));
+        }
+        vars.push((
+            "REQUEST_ID_FORMAT",
+            self.request_id_format.as_str().to_string(),
+        ));
+        if let Some(admin_port) = self.admin_port {
+            vars.push(("ADMIN_PORT", admin_port.to_string()));
+        }
+        vars.push(("SIGUSR1_FLUSH", self.sigusr1_flush.to_string()));
+        if let Some(require_header) = &self.require_header {
+            vars.push(("REQUIRE_HEADER", masked_required_header(require_header)));
+        }
+        vars.push((
+            "STARTUP_LOG_STYLE",
+            self.startup_log_style.as_str().to_string(),
+        ));
+        if let Some(read_timeout_ms) = self.read_timeout_ms {
+            vars.push(("READ_TIMEOUT_MS", read_timeout_ms.to_string()));
+        }
+        if let Some(write_timeout_ms) = self.write_timeout_ms {
+            vars.push(("WRITE_TIMEOUT_MS", write_timeout_ms.to_string()));
+        }
+        vars.push(("CANARY", self.canary.to_string()));
+        if let Some(admin_hmac_key) = &self.admin_hmac_key {
+            vars.push(("ADMIN_HMAC_KEY", mask(admin_hmac_key)));
+        }
+
+        vars.into_iter()
+            .map(|(key, value)| format!("export {key}={}\n", shell_escape(&value)))
+            .collect()
+    }
+}
+
+/// Composes the Datadog tags. Every tag goes through [`merge_tags`], so there's a single one
+/// per key, the one with the highest precedence:
+///
+/// 1. The defaults: `service:<service>` (`DD_SERVICE`, the crate name by default, so it
+///    matches the `service` of the logs), and `env:local` and `local:true` in debug builds
+///    without an explicit `env` (`DD_ENV` or an `env:` user tag), so logs shipped while
+///    developing are easy to filter out.
+/// 2. The user tags, from `tag_sources` (later sources win).
+/// 3. What's known about the deployment and the build: `env` from `DD_ENV`, `version`,
+///    `pod_name`, `build.time` and `git.branch`.
+pub fn compose_tags(
+    tag_sources: &[Option<&str>],
+    append_version: bool,
+    service: &str,
+    pod_name: Option<&str>,
+    dd_env: Option<&str>,
+) -> String {
+    let explicit_env = dd_env.is_some()
+        || merge_tags(tag_sources)
+            .iter()
+            .any(|tag| tag_key(tag) == "env");
+    let mut defaults = vec![format!("service:{service}")];
+    if !explicit_env && cfg!(debug_assertions) {
+        defaults.push("env:local".to_string());
+        defaults.push("local:true".to_string());
+    }
+
+    let mut generated = Vec::new();
+    if let Some(dd_env) = dd_env {
+        generated.push(format!("env:{dd_env}"));
+    }
+    if append_version {
+        generated.push(VERSION.to_string());
+    }
+    if let Some(pod_name) = pod_name {
+        generated.push(format!("pod_name:{pod_name}"));
+    }
+    generated.push(format!("build.time:{}", build_info::BUILD_TIMESTAMP));
+    generated.push(format!("git.branch:{}", build_info::GIT_BRANCH));
+
+    let defaults = defaults.join(",");
+    let generated = generated.join(",");
+    let mut sources = vec![Some(defaults.as_str())];
+    sources.extend_from_slice(tag_sources);
+    sources.push(Some(generated.as_str()));
+    merge_tags(&sources).join(",")
+}
+
+/// Merges comma-separated tag lists. A tag from a later source replaces the earlier ones with
+/// the same key (the part before the `:`), keeping the position of the first one. Exact
+/// duplicates are removed.
+pub fn merge_tags(sources: &[Option<&str>]) -> Vec<String> {
+    let mut merged: Vec<String> = Vec::new();
+    let tags = sources
+        .iter()
+        .flatten()
+        .flat_map(|tags| tags.split(','))
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty());
+    for tag in tags {
+        match merged
+            .iter_mut()
+            .find(|merged| tag_key(merged) == tag_key(tag))
+        {
+            Some(existing) => *existing = tag.to_string(),
+            None => merged.push(tag.to_string()),
+        }
+    }
+    merged
+}
+
+fn tag_key(tag: &str) -> &str {
+    tag.split_once(':').map_or(tag, |(key, _)| key)
+}
+
+/// Keeps the last 4 characters so the value can still be told apart.
+fn mask(value: &str) -> String {
+    let chars = value.chars().collect::<Vec<_>>();
+    let masked = chars.len().saturating_sub(4).max(chars.len() / 2);
+    let suffix = chars[masked..].iter().collect::<String>();
+    format!("{}{suffix}", "*".repeat(masked))
+}
+
+/// `REQUIRE_HEADER` as set, with the value masked.
+fn masked_required_header(required: &RequiredHeader) -> String {
+    match &required.value {
+        Some(value) => format!("{}={}", required.name, mask(value)),
+        None => required.name.clone(),
+    }
+}
+
+/// Single-quotes the value so it's taken literally by the shell.
+fn shell_escape(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Reads an optional secret. Empty or whitespace-only values are treated as missing.
+fn secret(secret_store: &Secrets, key: &str) -> Option<String> {
+    secret_store
+        .get(key)
+        .filter(|value| !value.trim().is_empty())
+}
+
+fn env_var(key: &str) -> Option<String> {
+    std::env::var(key)
+        .ok()
+        .filter(|value| !value.trim().is_empty())
+}
+
+/// Reads a secret that must be present and not empty.
+fn require_secret(secret_store: &Secrets, key: &str) -> anyhow::Result<String> {
+    secret(secret_store, key).with_context(|| format!("{key} not found or empty"))
+}
+
+/// Parses an optional secret, failing if it's present but invalid.
+fn parse_secret<T>(secret_store: &Secrets, key: &str) -> anyhow::Result<Option<T>>
+where
+    T: FromStr,
+    T::Err: std::error::Error + Send + Sync + 'static,
+{
+    secret(secret_store, key)
+        .map(|value| {
+            value
+                .trim()
+                .parse()
+                .with_context(|| format!("invalid {key}: {value}"))
+        })
+        .transpose()
+}
+
+/// Parses an optional boolean secret (`true`/`false`, `1`/`0`, `yes`/`no`).
+fn flag_secret(secret_store: &Secrets, key: &str) -> anyhow::Result<Option<bool>> {
+    secret(secret_store, key)
+        .map(|value| match value.trim().to_lowercase().as_str() {
+            "true" | "1" | "yes" => Ok(true),
+            "false" | "0" | "no" => Ok(false),
+            _ => anyhow::bail!("invalid {key}: {value}"),
+        })
+        .transpose()
+}
+
+/// Reads an optional comma-separated secret as a lowercased list.
+fn list_secret(secret_store: &Secrets, key: &str) -> Option<Vec<String>> {
+    secret(secret_store, key).map(|value| {
+        value
+            .split(',')
+            .map(|item| item.trim().to_lowercase())
+            .filter(|item| !item.is_empty())
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support;
+
+    fn config(pairs: &[(&str, &str)]) -> Config {
+        Config::from_secrets(&test_support::secrets(pairs)).unwrap()
+    }
+
+    #[test]
+    fn dd_api_url_defaults_to_the_region_one() {
+        assert_eq!(config(&[]).dd_api_url, "https://api.datadoghq.com");
+        assert_eq!(
+            config(&[("DD_REGION", "EU")]).dd_api_url,
+            "https://api.datadoghq.eu"
+        );
+        assert_eq!(
+            config(&[("DD_API_URL", "http://localhost:8126/")]).dd_api_url,
+            "http://localhost:8126"
+        );
+    }
+
+    #[test]
+    fn to_env_masks_the_sensitive_values() {
+        let env = config(&[("ADMIN_TOKEN", "admin-token-1234")]).to_env();
+
+        assert!(env.contains("export DD_API_KEY='********-key'\n"));
+        assert!(env.contains("export ADMIN_TOKEN='************1234'\n"));
+        assert!(!env.contains(test_support::API_KEY));
+    }
+
+    #[test]
+    fn to_env_values_are_shell_escaped() {
+        let env = config(&[("DD_TAGS", "team:it's me")]).to_env();
+
+        assert!(env.contains(r"export DD_TAGS='team:it'\''s me'"));
+    }
+
+    #[test]
+    fn mask_keeps_at_most_the_last_4_characters() {
+        assert_eq!(mask("abcdefgh"), "****efgh");
+        assert_eq!(mask("abcd"), "**cd");
+        assert_eq!(mask(""), "");
+    }
+
+    #[test]
+    fn debug_masks_the_sensitive_values() {
+        let debug = format!("{:?}", config(&[("ADMIN_TOKEN", "admin-token-1234")]));
+
+        for secret in [test_support::API_KEY, "admin-token-1234"] {
+            assert!(!debug.contains(secret), "{secret} in {debug}");
+        }
+        assert!(debug.contains("admin_token: Some(\"************1234\")"));
+        assert!(debug.contains("log_level: \"INFO\""));
+    }
+
+    #[test]
+    fn empty_required_secrets_are_missing() {
+        for empty in ["", "   "] {
+            let error = Config::from_secrets(&test_support::secrets(&[("DD_API_KEY", empty)]))
+                .expect_err("an empty DD_API_KEY was accepted");
+            assert_eq!(error.to_string(), "DD_API_KEY not found or empty");
+        }
+    }
+
+    #[test]
+    fn empty_optional_secrets_are_missing() {
+        let secrets = test_support::secrets(&[("DD_TAGS", " "), ("LOG_LEVEL", "")]);
+
+        assert_eq!(secret(&secrets, "DD_TAGS"), None);
+        assert_eq!(config(&[("LOG_LEVEL", "")]).log_level, "INFO");
+    }
+
+    /// The tags composed from `user_tags`.
+    fn tags(user_tags: &str) -> Vec<String> {
+        compose_tags(&[Some(user_tags)], true, DEFAULT_SERVICE, None, None)
+            .split(',')
+            .map(str::to_string)
+            .collect()
+    }
+
+    fn tag<'a>(tags: &'a [String], key: &str) -> Option<&'a str> {
+        tags.iter()
+            .find_map(|tag| tag.strip_prefix(key)?.strip_prefix(':'))
+    }
+
+    #[test]
+    fn build_time_tag_is_a_timestamp() {
+        let tags = tags("");
+
+        let build_time = tag(&tags, "build.time").expect("no build.time tag");
+        assert_eq!(build_time, build_info::BUILD_TIMESTAMP);
+        time::OffsetDateTime::parse(build_time, &time::format_description::well_known::Rfc3339)
+            .expect("build.time isn't RFC 3339");
+    }
+
+    #[test]
+    fn git_branch_tag_is_present() {
+        let tags = tags("");
+
+        assert_eq!(tag(&tags, "git.branch"), Some(build_info::GIT_BRANCH));
+        assert!(!build_info::GIT_BRANCH.is_empty());
+    }
+
+    #[test]
+    fn version_tag_can_be_disabled() {
+        let has_version = |config: Config| config.tags.split(',').any(|tag| tag == VERSION);
+
+        assert!(has_version(config(&[])));
+        assert!(has_version(config(&[("DD_APPEND_VERSION", "true")])));
+        assert!(!has_version(config(&[("DD_APPEND_VERSION", "false")])));
+    }
+
+    #[test]
+    fn version_tag_replaces_the_user_one() {
+        let tags = tags("version:9.9.9");
+
+        assert_eq!(tag(&tags, "version"), Some("0.1.0"));
+        assert_eq!(
+            tags.iter().filter(|tag| tag_key(tag) == "version").count(),
+            1
+        );
+    }
+
+    #[test]
+    fn max_header_bytes_has_a_minimum() {
+        let secrets = test_support::secrets(&[("MAX_HEADER_BYTES", "1024")]);
+
+        assert!(Config::from_secrets(&secrets).is_err());
+        assert_eq!(
+            config(&[("MAX_HEADER_BYTES", "16384")]).max_header_bytes,
+            Some(16384)
+        );
+    }
+
+    #[test]
+    fn pod_name_is_tagged_when_enabled() {
+        // the only tests setting it, both to the same value
+        std::env::set_var("POD_NAME", "web-7d9f");
+
+        let enabled = config(&[("TAG_POD_NAME", "true")]);
+        assert_eq!(enabled.pod_name.as_deref(), Some("web-7d9f"));
+        assert!(enabled
+            .tags
+            .split(',')
+            .any(|tag| tag == "pod_name:web-7d9f"));
+
+        let disabled = config(&[]);
+        assert_eq!(disabled.pod_name, None);
+        assert!(!disabled.tags.contains("pod_name:"));
+    }
+
+    #[test]
+    fn local_tags_are_only_added_to_debug_builds() {
+        let tags = tags("team:web");
+
+        if cfg!(debug_assertions) {
+            assert_eq!(tag(&tags, "env"), Some("local"));
+            assert_eq!(tag(&tags, "local"), Some("true"));
+        } else {
+            assert_eq!(tag(&tags, "env"), None);
+            assert_eq!(tag(&tags, "local"), None);
+        }
+    }
+
+    #[test]
+    fn explicit_env_skips_the_local_tags() {
+        for tags in [
+            compose_tags(
+                &[Some("team:web")],
+                true,
+                DEFAULT_SERVICE,
+                None,
+                Some("prod"),
+            ),
+            compose_tags(&[Some("env:prod")], true, DEFAULT_SERVICE, None, None),
+        ] {
+            let tags = tags.split(',').map(str::to_string).collect::<Vec<_>>();
+            assert_eq!(tag(&tags, "env"), Some("prod"));
+            assert_eq!(tag(&tags, "local"), None);
+        }
+    }
+
+    #[test]
+    fn later_tag_sources_override_by_key() {
+        let merged = merge_tags(&[
+            Some("team:web,tier:1,region:eu"),
+            None,
+            Some("tier:2, team:web"),
+            Some("region:us,extra:yes"),
+        ]);
+
+        assert_eq!(merged, ["team:web", "tier:2", "region:us", "extra:yes"]);
+    }
+
+    #[test]
+    fn dd_tags_env_and_extra_are_merged_over_dd_tags() {
+        let tags = config(&[
+            ("DD_TAGS", "team:web,tier:1"),
+            ("DD_TAGS_ENV", "tier:2,zone:a"),
+            ("DD_TAGS_EXTRA", "zone:b"),
+        ])
+        .tags
+        .split(',')
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+
+        assert_eq!(tag(&tags, "team"), Some("web"));
+        assert_eq!(tag(&tags, "tier"), Some("2"));
+        assert_eq!(tag(&tags, "zone"), Some("b"));
+        let mut keys = tags.iter().map(|tag| tag_key(tag)).collect::<Vec<_>>();
+        keys.sort_unstable();
+        keys.dedup();
+        assert_eq!(keys.len(), tags.len(), "{tags:?}");
+    }
+
+    #[test]
+    fn to_env_masks_the_required_header_value() {
+        let env = config(&[("REQUIRE_HEADER", "X-Api-Gateway=gateway-secret")]).to_env();
+
+        assert!(env.contains("export REQUIRE_HEADER='x-api-gateway=**********cret'\n"));
+        assert!(!env.contains("gateway-secret"));
+    }
+
+    #[test]
+    fn service_tag_defaults_to_the_crate_name() {
+        assert_eq!(tag(&tags(""), "service"), Some(env!("CARGO_PKG_NAME")));
+
+        let tags = tags("service:my-fork");
+        assert_eq!(tag(&tags, "service"), Some("my-fork"));
+        assert_eq!(
+            tags.iter().filter(|tag| tag_key(tag) == "service").count(),
+            1
+        );
+    }
+
+    #[test]
+    fn service_tag_follows_dd_service() {
+        let tags = |pairs| {
+            config(pairs)
+                .tags
+                .split(',')
+                .map(str::to_string)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            tag(&tags(&[("DD_SERVICE", "Billing API")]), "service"),
+            Some("billing-api")
+        );
+        assert_eq!(
+            tag(
+                &tags(&[("DD_SERVICE", "billing"), ("DD_TAGS", "service:override")]),
+                "service"
+            ),
+            Some("override")
+        );
+    }
+}
@@ -0,0 +1,865 @@
+use crate::telemetry;
+use flate2::write::{DeflateEncoder, GzEncoder};
+use serde_json::{json, Map, Value};
+use std::{
+    cell::Cell,
+    fmt,
+    io::{self, Write},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    thread::JoinHandle,
+    time::Duration,
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{
+    field::{Field, Visit},
+    span, Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, registry::LookupSpan, Layer};
+
+/// A log as shipped to Datadog.
+pub type Log = Map<String, Value>;
+
+/// Target of the logs about shipping the logs. They're local only, the Datadog layers drop
+/// them: they'd fail to be shipped the same way.
+pub const TARGET: &str = "datadog";
+
+/// `ddsource` of the logs, kept from `dd-tracing-layer` so existing pipelines still match.
+const DD_SOURCE: &str = "dd-tracing-layer";
+/// Datadog takes at most 1000 logs per request.
+const MAX_BATCH_SIZE: usize = 1000;
+const MAX_BATCH_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_RETRIES: u32 = 3;
+/// Wait before the first retry, doubled on each of the next ones.
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+thread_local! {
+    /// Set on the Datadog threads. What's logged there (e.g. by reqwest) isn't shipped, or
+    /// each batch would make another one.
+    static SHIPPING: Cell<bool> = const { Cell::new(false) };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    US1,
+    US3,
+    US5,
+    US1FED,
+    EU,
+}
+
+/// Name and Datadog site of each region. The API and intake hosts are subdomains of the site.
+const REGIONS: [(Region, &str, &str); 5] = [
+    (Region::US1, "US1", "datadoghq.com"),
+    (Region::US3, "US3", "us3.datadoghq.com"),
+    (Region::US5, "US5", "us5.datadoghq.com"),
+    (Region::US1FED, "US1FED", "ddog-gov.com"),
+    (Region::EU, "EU", "datadoghq.eu"),
+];
+
+impl Region {
+    fn site(self) -> &'static str {
+        REGIONS
+            .iter()
+            .find(|(region, ..)| *region == self)
+            .map(|(.., site)| *site)
+            .expect("every region has a site")
+    }
+}
+
+pub fn parse_region(region: &str) -> anyhow::Result<Region> {
+    let name = region.trim().to_uppercase();
+    REGIONS
+        .iter()
+        .find(|(_, region_name, _)| *region_name == name)
+        .map(|(region, ..)| *region)
+        .ok_or_else(|| anyhow::anyhow!("unknown Datadog region: {region}"))
+}
+
+/// Base URL of the Datadog API for the given region.
+pub fn api_url(region: Region) -> String {
+    format!("https://api.{}", region.site())
+}
+
+/// Checks the API key against the `/api/v1/validate` endpoint of the Datadog API at `base_url`,
+/// see [`api_url`].
+///
+/// Returns `Ok(false)` when Datadog rejects the key, and an error if it couldn't tell.
+pub async fn validate_api_key(
+    client: &reqwest::Client,
+    base_url: &str,
+    api_key: &str,
+) -> Result<bool, reqwest::Error> {
+    let res = client
+        .get(format!("{base_url}/api/v1/validate"))
+        .header("DD-API-KEY", api_key)
+        .send()
+        .await?;
+
+    match res.status() {
+        reqwest::StatusCode::UNAUTHORIZED | reqwest::StatusCode::FORBIDDEN => Ok(false),
+        _ => res.error_for_status().map(|_| true),
+    }
+}
+
+/// Logs intake URL for the given region.
+pub fn intake_url(region: Region) -> String {
+    format!("https://http-intake.logs.{}/api/v2/logs", region.site())
+}
+
+/// Encoding of the payloads sent to the intake.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Deflate,
+}
+
+impl Compression {
+    pub fn parse(compression: &str) -> anyhow::Result<Self> {
+        match compression.trim().to_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "gzip" => Ok(Self::Gzip),
+            "deflate" => Ok(Self::Deflate),
+            "zstd" => anyhow::bail!("DD_COMPRESSION=zstd isn't supported by the Datadog logs intake, only gzip and deflate are"),
+            _ => anyhow::bail!("invalid DD_COMPRESSION: {compression}"),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Gzip => "gzip",
+            Self::Deflate => "deflate",
+        }
+    }
+
+    /// `Content-Encoding` of the compressed payloads.
+    fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            Self::None => None,
+            Self::Gzip => Some("gzip"),
+            Self::Deflate => Some("deflate"),
+        }
+    }
+
+    fn compress(&self, body: Vec<u8>) -> io::Result<Vec<u8>> {
+        let level = flate2::Compression::default();
+        match self {
+            Self::None => Ok(body),
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), level);
+                encoder.write_all(&body)?;
+                encoder.finish()
+            }
+            Self::Deflate => {
+                let mut encoder = DeflateEncoder::new(Vec::new(), level);
+                encoder.write_all(&body)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Counters of the Datadog layers, exposed in `/metrics`.
+#[derive(Debug, Default)]
+struct Stats {
+    /// Logs waiting to be sent.
+    queued: AtomicU64,
+    /// Logs Datadog took.
+    shipped: AtomicU64,
+    /// Logs Datadog rejected, or that couldn't be sent after the retries.
+    failed: AtomicU64,
+}
+
+impl Stats {
+    fn dequeued(&self, count: usize) {
+        // the ingestor can be fed directly, without going through a layer
+        let _ = self
+            .queued
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |queued| {
+                Some(queued.saturating_sub(count as u64))
+            });
+    }
+}
+
+/// Handle on the Datadog layers, to read their counters and flush them.
+///
+/// The default one has no layer, everything is `0`.
+#[derive(Clone, Default)]
+pub struct DatadogHandle {
+    stats: Arc<Stats>,
+    // weak, so dropping the layers still stops their thread
+    ingestors: Vec<mpsc::WeakUnboundedSender<Message>>,
+}
+
+impl DatadogHandle {
+    /// Starts a layer shipping its events through `ingestor`, counted and flushed by this
+    /// handle.
+    pub fn layer(&mut self, mut ingestor: Ingestor) -> DatadogLayer {
+        ingestor.stats = self.stats.clone();
+        let layer = DatadogLayer::new(ingestor);
+        self.ingestors
+            .extend(layer.tx.as_ref().map(|tx| tx.downgrade()));
+        layer
+    }
+
+    /// Logs waiting to be sent.
+    pub fn queue_depth(&self) -> u64 {
+        self.stats.queued.load(Ordering::Relaxed)
+    }
+
+    /// Logs Datadog took.
+    pub fn shipped(&self) -> u64 {
+        self.stats.shipped.load(Ordering::Relaxed)
+    }
+
+    /// Logs Datadog rejected, or that couldn't be sent.
+    pub fn failed(&self) -> u64 {
+        self.stats.failed.load(Ordering::Relaxed)
+    }
+
+    /// Sends everything the layers have queued, returning how many logs Datadog took.
+    pub async fn flush(&self) -> u64 {
+        let mut flushed = 0;
+        for ingestor in &self.ingestors {
+            let (done, flushed_logs) = oneshot::channel();
+            let sent = ingestor
+                .upgrade()
+                .is_some_and(|ingestor| ingestor.send(Message::Flush(done)).is_ok());
+            if sent {
+                flushed += flushed_logs.await.unwrap_or_default();
+            }
+        }
+        flushed
+    }
+}
+
+enum Message {
+    Log(Log),
+    /// Answered with the number of logs Datadog took.
+    Flush(oneshot::Sender<u64>),
+}
+
+/// Fields of a span, as shipped.
+struct SpanFields(Log);
+
+/// Turns the events into logs shipped by its [`Ingestor`], on a thread of its own.
+///
+/// Every log carries the fields of its spans, including the ones recorded after the span
+/// was created (`Span::record`).
+pub struct DatadogLayer {
+    location: bool,
+    target_prefix: Option<String>,
+    stats: Arc<Stats>,
+    tx: Option<mpsc::UnboundedSender<Message>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl DatadogLayer {
+    fn new(ingestor: Ingestor) -> Self {
+        let stats = ingestor.stats.clone();
+        let (tx, rx) = mpsc::unbounded_channel();
+        let worker = std::thread::Builder::new()
+            .name("datadog".into())
+            .spawn(move || ingestor.run(rx))
+            .expect("failed to spawn the Datadog thread");
+        Self {
+            location: false,
+            target_prefix: None,
+            stats,
+            tx: Some(tx),
+            worker: Some(worker),
+        }
+    }
+
+    /// Adds the `file` and `line` of the caller to the logs.
+    pub fn with_location(mut self, location: bool) -> Self {
+        self.location = location;
+        self
+    }
+
+    /// Ships the targets as `<prefix>::<target>`.
+    pub fn with_target_prefix(mut self, target_prefix: Option<String>) -> Self {
+        self.target_prefix = target_prefix;
+        self
+    }
+
+    fn log<S>(&self, event: &Event<'_>, ctx: &Context<'_, S>) -> Log
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let mut log = Log::new();
+        let spans = ctx
+            .event_scope(event)
+            .into_iter()
+            .flat_map(|scope| scope.from_root())
+            .map(|span| {
+                let mut fields = Log::from_iter([("name".to_string(), json!(span.name()))]);
+                if let Some(SpanFields(recorded)) = span.extensions().get::<SpanFields>() {
+                    fields.extend(recorded.clone());
+                }
+                Value::Object(fields)
+            })
+            .collect::<Vec<_>>();
+        if let Some(last) = spans.last() {
+            log.insert("span".into(), last.clone());
+            log.insert("spans".into(), Value::Array(spans));
+        }
+
+        let metadata = event.metadata();
+        log.insert("level".into(), json!(metadata.level().as_str()));
+        let target = match &self.target_prefix {
+            Some(prefix) => format!("{prefix}::{}", metadata.target()),
+            None => metadata.target().to_string(),
+        };
+        log.insert("target".into(), json!(target));
+        if self.location {
+            log.insert("file".into(), json!(metadata.file()));
+            log.insert("line".into(), json!(metadata.line()));
+        }
+        event.record(&mut JsonVisitor(&mut log));
+        let timestamp = OffsetDateTime::now_utc()
+            .format(&Rfc3339)
+            .unwrap_or_default();
+        log.insert("timestamp".into(), json!(timestamp));
+        log
+    }
+}
+
+impl Drop for DatadogLayer {
+    fn drop(&mut self) {
+        // closing the channel makes the thread send what's left and stop
+        self.tx.take();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl<S> Layer<S> for DatadogLayer
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("span not found, this is a bug");
+        let mut extensions = span.extensions_mut();
+        // the `DD_ERROR_TAGS` layer sees the same spans
+        if extensions.get_mut::<SpanFields>().is_none() {
+            let mut fields = Log::new();
+            attrs.record(&mut JsonVisitor(&mut fields));
+            extensions.insert(SpanFields(fields));
+        }
+    }
+
+    fn on_record(&self, id: &span::Id, values: &span::Record<'_>, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("span not found, this is a bug");
+        let mut extensions = span.extensions_mut();
+        if let Some(SpanFields(fields)) = extensions.get_mut::<SpanFields>() {
+            values.record(&mut JsonVisitor(fields));
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if SHIPPING.with(Cell::get) {
+            return;
+        }
+        if let Some(tx) = &self.tx {
+            if tx.send(Message::Log(self.log(event, &ctx))).is_ok() {
+                self.stats.queued.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Records the fields as JSON values.
+pub struct JsonVisitor<'a>(pub &'a mut Map<String, Value>);
+
+impl Visit for JsonVisitor<'_> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().into(), json!(value));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().into(), json!(value));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().into(), json!(value));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().into(), json!(value));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().into(), json!(value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().into(), json!(format!("{value:?}")));
+    }
+}
+
+/// Ships the logs to the Datadog intake, the same way `dd-tracing-layer` does, adding the
+/// `log_schema_version` field and the `tenant:<id>` tag of the request.
+///
+/// Logs are sent in batches of [`MAX_BATCH_SIZE`], or every [`MAX_BATCH_INTERVAL`]. The
+/// failed requests are retried with a backoff.
+#[derive(Clone)]
+pub struct Ingestor {
+    url: String,
+    api_key: String,
+    service_name: String,
+    tags: String,
+    compression: Compression,
+    client: reqwest::Client,
+    queue: Arc<Mutex<Vec<Log>>>,
+    /// Held while sending the queue, so the last flush waits for the batch the interval may
+    /// be sending instead of the runtime dropping it.
+    sending: Arc<tokio::sync::Mutex<()>>,
+    stats: Arc<Stats>,
+}
+
+impl Ingestor {
+    pub fn new(url: &str, api_key: &str, service_name: &str, tags: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            api_key: api_key.to_string(),
+            service_name: service_name.to_string(),
+            tags: tags.to_string(),
+            compression: Compression::Gzip,
+            client: reqwest::Client::new(),
+            queue: Arc::default(),
+            sending: Arc::default(),
+            stats: Arc::default(),
+        }
+    }
+
+    pub fn with_compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Ingests what the layer sends until it's dropped, then sends what's left.
+    fn run(self, mut rx: mpsc::UnboundedReceiver<Message>) {
+        SHIPPING.with(|shipping| shipping.set(true));
+        let runtime = match tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+        {
+            Ok(runtime) => runtime,
+            Err(e) => {
+                tracing::error!(target: TARGET, error = %e, "Failed to start the Datadog runtime, logs won't be shipped");
+                return;
+            }
+        };
+        runtime.block_on(async move {
+            let this = self.clone();
+            tokio::spawn(async move {
+                let mut ticks = tokio::time::interval(MAX_BATCH_INTERVAL);
+                // the first tick is immediate, there's nothing to send yet
+                ticks.tick().await;
+                loop {
+                    ticks.tick().await;
+                    this.send_queued().await;
+                }
+            });
+            while let Some(message) = rx.recv().await {
+                match message {
+                    Message::Log(log) => self.ingest(log).await,
+                    Message::Flush(done) => {
+                        let _ = done.send(self.send_queued().await);
+                    }
+                }
+            }
+            self.send_queued().await;
+        });
+    }
+
+    async fn ingest(&self, mut log: Log) {
+        let tags = match tenant_id(&log) {
+            Some(tenant_id) if self.tags.is_empty() => format!("tenant:{tenant_id}"),
+            Some(tenant_id) => format!("{},tenant:{tenant_id}", self.tags),
+            None => self.tags.clone(),
+        };
+        log.insert("ddsource".into(), json!(DD_SOURCE));
+        log.insert("ddtags".into(), json!(tags));
+        log.insert("service".into(), json!(self.service_name));
+        log.insert(
+            "log_schema_version".into(),
+            json!(telemetry::LOG_SCHEMA_VERSION),
+        );
+        let full = {
+            let mut queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+            queue.push(log);
+            queue.len() >= MAX_BATCH_SIZE
+        };
+        if full {
+            let batch = self.take_batch();
+            self.send(batch).await;
+        }
+    }
+
+    fn take_batch(&self) -> Vec<Log> {
+        let mut queue = self.queue.lock().unwrap_or_else(PoisonError::into_inner);
+        let len = queue.len().min(MAX_BATCH_SIZE);
+        self.stats.dequeued(len);
+        queue.drain(..len).collect()
+    }
+
+    /// Sends everything queued, returning how many logs Datadog took.
+    async fn send_queued(&self) -> u64 {
+        let _sending = self.sending.lock().await;
+        let mut shipped = 0;
+        loop {
+            let batch = self.take_batch();
+            if batch.is_empty() {
+                return shipped;
+            }
+            shipped += self.send(batch).await;
+        }
+    }
+
+    /// Sends `logs`, retrying what Datadog says should be, and splitting the batches that are
+    /// too large. Returns how many logs Datadog took.
+    async fn send(&self, logs: Vec<Log>) -> u64 {
+        let mut shipped = 0;
+        let mut pending = vec![(logs, 0)];
+        while let Some((logs, retries)) = pending.pop() {
+            let count = logs.len() as u64;
+            match self.post(&logs).await {
+                Ok(status) if status.is_success() => shipped += count,
+                // the payload or the API key is wrong, it won't get better
+                Ok(status) if [400, 401, 403].contains(&status.as_u16()) => {
+                    tracing::error!(target: TARGET, %status, logs = count, "Datadog rejected the logs");
+                    self.stats.failed.fetch_add(count, Ordering::Relaxed);
+                }
+                Ok(status) if status.as_u16() == 413 && logs.len() > 1 => {
+                    let mut left = logs;
+                    let right = left.split_off(left.len() / 2);
+                    pending.push((left, retries));
+                    pending.push((right, retries));
+                }
+                Ok(status) if status.as_u16() == 413 => {
+                    tracing::error!(target: TARGET, "A log is too large for Datadog, dropping it");
+                    self.stats.failed.fetch_add(count, Ordering::Relaxed);
+                }
+                result if retries < MAX_RETRIES => {
+                    let backoff = RETRY_BACKOFF * 2u32.pow(retries);
+                    match result {
+                        Ok(status) => {
+                            tracing::warn!(target: TARGET, %status, ?backoff, "Datadog failed to take the logs, retrying")
+                        }
+                        Err(e) => {
+                            tracing::warn!(target: TARGET, error = %e, ?backoff, "Failed to send the logs to Datadog, retrying")
+                        }
+                    }
+                    tokio::time::sleep(backoff).await;
+                    pending.push((logs, retries + 1));
+                }
+                _ => {
+                    tracing::error!(target: TARGET, logs = count, retries, "Failed to send the logs to Datadog, dropping them");
+                    self.stats.failed.fetch_add(count, Ordering::Relaxed);
+                }
+            }
+        }
+        self.stats.shipped.fetch_add(shipped, Ordering::Relaxed);
+        shipped
+    }
+
+    async fn post(&self, logs: &[Log]) -> anyhow::Result<reqwest::StatusCode> {
+        let body = self.compression.compress(serde_json::to_vec(logs)?)?;
+        let mut req = self
+            .client
+            .post(&self.url)
+            .header("DD-API-KEY", &self.api_key)
+            .header(reqwest::header::CONTENT_TYPE, "application/json");
+        if let Some(encoding) = self.compression.content_encoding() {
+            req = req.header(reqwest::header::CONTENT_ENCODING, encoding);
+        }
+        Ok(req.body(body).send().await?.status())
+    }
+}
+
+/// `tenant_id` of the innermost span having one.
+fn tenant_id(log: &Log) -> Option<&str> {
+    log.get("spans")?
+        .as_array()?
+        .iter()
+        .rev()
+        .find_map(|span| span.get("tenant_id")?.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{mock_datadog, mock_intake, Batches};
+    use axum::http::StatusCode;
+    use tracing_subscriber::{filter::LevelFilter, prelude::*, Registry};
+
+    fn log(message: &str) -> Log {
+        Log::from_iter([("message".to_string(), json!(message))])
+    }
+
+    /// Every log shipped to the intake.
+    fn shipped(batches: &Batches) -> Vec<Log> {
+        let batches = batches.lock().unwrap();
+        batches
+            .iter()
+            .flat_map(|batch| batch.logs.clone())
+            .collect()
+    }
+
+    #[test]
+    fn regions_have_their_urls() {
+        let region = parse_region(" eu ").unwrap();
+        assert_eq!(region, Region::EU);
+        assert_eq!(api_url(region), "https://api.datadoghq.eu");
+        assert_eq!(
+            intake_url(region),
+            "https://http-intake.logs.datadoghq.eu/api/v2/logs"
+        );
+        assert_eq!(api_url(Region::US1FED), "https://api.ddog-gov.com");
+        assert!(parse_region("mars").is_err());
+    }
+
+    #[tokio::test]
+    async fn validate_api_key_against_mock_endpoint() {
+        let base_url = mock_datadog();
+        let client = reqwest::Client::new();
+
+        assert!(validate_api_key(&client, &base_url, "valid-key")
+            .await
+            .unwrap());
+        assert!(!validate_api_key(&client, &base_url, "wrong-key")
+            .await
+            .unwrap());
+        assert!(validate_api_key(&client, &base_url, "broken-key")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn shipped_logs_have_the_datadog_fields_and_the_schema_version() {
+        let (url, batches) = mock_intake(|_| StatusCode::ACCEPTED);
+        let ingestor = Ingestor::new(&url, "api-key", "test-service", "env:test,team:web");
+
+        ingestor.ingest(log("hello")).await;
+        ingestor.send_queued().await;
+
+        let shipped = &shipped(&batches)[0];
+        assert_eq!(shipped["message"], "hello");
+        assert_eq!(shipped["ddsource"], DD_SOURCE);
+        assert_eq!(shipped["ddtags"], "env:test,team:web");
+        assert_eq!(shipped["service"], "test-service");
+        assert_eq!(shipped["log_schema_version"], telemetry::LOG_SCHEMA_VERSION);
+    }
+
+    #[tokio::test]
+    async fn too_large_batches_are_split() {
+        let (url, batches) = mock_intake(|logs| {
+            if logs.len() > 1 {
+                StatusCode::PAYLOAD_TOO_LARGE
+            } else {
+                StatusCode::ACCEPTED
+            }
+        });
+        let ingestor = Ingestor::new(&url, "api-key", "test-service", "");
+
+        for message in ["a", "b", "c"] {
+            ingestor.ingest(log(message)).await;
+        }
+        assert_eq!(ingestor.send_queued().await, 3);
+
+        let batches = batches.lock().unwrap();
+        let accepted = batches
+            .iter()
+            .filter(|batch| batch.logs.len() == 1)
+            .map(|batch| batch.logs[0]["message"].as_str().unwrap())
+            .collect::<std::collections::BTreeSet<_>>();
+        assert_eq!(accepted, ["a", "b", "c"].into());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rejected_logs_are_not_retried_but_failures_are_with_a_backoff() {
+        let (url, batches) = mock_intake(|_| StatusCode::FORBIDDEN);
+        let ingestor = Ingestor::new(&url, "api-key", "test-service", "");
+        ingestor.ingest(log("rejected")).await;
+        ingestor.send_queued().await;
+        assert_eq!(batches.lock().unwrap().len(), 1);
+
+        let (url, batches) = mock_intake(|_| StatusCode::SERVICE_UNAVAILABLE);
+        let ingestor = Ingestor::new(&url, "api-key", "test-service", "");
+        ingestor.ingest(log("failed")).await;
+        let start = tokio::time::Instant::now();
+        ingestor.send_queued().await;
+        assert_eq!(batches.lock().unwrap().len(), MAX_RETRIES as usize + 1);
+        // 500ms, 1s and 2s
+        assert!(start.elapsed() >= RETRY_BACKOFF * 7);
+    }
+
+    #[tokio::test]
+    async fn logs_of_a_tenant_get_its_tag() {
+        let (url, batches) = mock_intake(|_| StatusCode::ACCEPTED);
+        let ingestor = Ingestor::new(&url, "api-key", "test-service", "env:test");
+        let mut scoped = log("scoped");
+        scoped.insert(
+            "spans".into(),
+            json!([{ "name": "request", "tenant_id": "acme" }, { "name": "handler" }]),
+        );
+
+        ingestor.ingest(scoped).await;
+        ingestor.ingest(log("unscoped")).await;
+        ingestor.send_queued().await;
+
+        let shipped = shipped(&batches);
+        assert_eq!(shipped[0]["ddtags"], "env:test,tenant:acme");
+        assert_eq!(shipped[1]["ddtags"], "env:test");
+    }
+
+    #[tokio::test]
+    async fn payloads_are_compressed_with_the_chosen_codec() {
+        for (compression, content_encoding) in [
+            (Compression::None, None),
+            (Compression::Gzip, Some("gzip")),
+            (Compression::Deflate, Some("deflate")),
+        ] {
+            let (url, batches) = mock_intake(|_| StatusCode::ACCEPTED);
+            let ingestor = Ingestor::new(&url, "api-key", "test", "").with_compression(compression);
+
+            ingestor.ingest(log("hello")).await;
+            ingestor.send_queued().await;
+
+            let batches = batches.lock().unwrap();
+            assert_eq!(
+                batches[0].content_encoding.as_deref(),
+                content_encoding,
+                "{compression:?}"
+            );
+            assert_eq!(batches[0].logs[0]["message"], "hello");
+        }
+        assert!(Compression::parse("zstd").is_err());
+        assert_eq!(Compression::parse(" GZIP ").unwrap(), Compression::Gzip);
+    }
+
+    /// The events of the tests only, not the ones of the mock intake.
+    fn subscriber(layer: DatadogLayer) -> impl Subscriber {
+        Registry::default().with(layer.with_filter(LevelFilter::INFO))
+    }
+
+    /// Ships what's logged by `f` through a layer set up by `configure`, returning the logs
+    /// Datadog got.
+    async fn ship(
+        configure: impl FnOnce(DatadogLayer) -> DatadogLayer,
+        f: impl FnOnce(),
+    ) -> Vec<Log> {
+        let (url, batches) = mock_intake(|_| StatusCode::ACCEPTED);
+        let mut handle = DatadogHandle::default();
+        let layer = configure(handle.layer(Ingestor::new(&url, "api-key", "test", "")));
+        // dropping the layer waits for its thread, which can't reach the intake if it's
+        // still got logs to send: they're flushed before
+        let guard = tracing::subscriber::set_default(subscriber(layer));
+        f();
+        handle.flush().await;
+        drop(guard);
+        shipped(&batches)
+    }
+
+    #[tokio::test]
+    async fn every_log_has_the_fields_of_its_spans() {
+        let logs = ship(
+            |layer| layer,
+            || {
+                let span = tracing::info_span!(
+                    "request",
+                    tenant_id = "acme",
+                    http.status_code = tracing::field::Empty
+                );
+                span.in_scope(|| {
+                    tracing::info!("first");
+                    tracing::info_span!("handler").in_scope(|| tracing::info!("second"));
+                });
+                span.record("http.status_code", 200);
+                span.in_scope(|| tracing::info!("last"));
+            },
+        )
+        .await;
+
+        assert_eq!(logs.len(), 3);
+        for log in &logs {
+            assert_eq!(log["spans"][0]["name"], "request");
+            assert_eq!(log["spans"][0]["tenant_id"], "acme", "{}", log["message"]);
+        }
+        assert_eq!(logs[1]["span"]["name"], "handler");
+        assert!(logs[0]["spans"][0].get("http.status_code").is_none());
+        assert_eq!(logs[2]["spans"][0]["http.status_code"], 200);
+    }
+
+    #[tokio::test]
+    async fn targets_get_the_prefix() {
+        let configure = |layer: DatadogLayer| layer.with_target_prefix(Some("billing".into()));
+        let logs = ship(configure, || tracing::info!("prefixed")).await;
+
+        assert_eq!(logs[0]["target"], format!("billing::{}", module_path!()));
+    }
+
+    #[tokio::test]
+    async fn location_is_only_shipped_when_enabled() {
+        let logs = ship(|layer| layer, || tracing::info!("hello")).await;
+        assert!(logs[0].get("file").is_none());
+        assert!(logs[0].get("line").is_none());
+
+        let logs = ship(
+            |layer| layer.with_location(true),
+            || tracing::info!("hello"),
+        )
+        .await;
+        assert_eq!(logs[0]["file"], file!());
+        assert!(logs[0]["line"].is_u64());
+    }
+
+    #[tokio::test]
+    async fn queue_depth_follows_the_logs_waiting_to_be_sent() {
+        let (url, batches) = mock_intake(|_| StatusCode::ACCEPTED);
+        let mut handle = DatadogHandle::default();
+        let layer = handle.layer(Ingestor::new(&url, "api-key", "test", ""));
+        let _guard = tracing::subscriber::set_default(subscriber(layer));
+
+        assert_eq!(handle.queue_depth(), 0);
+        tracing::info!("one");
+        tracing::info!("two");
+        assert_eq!(handle.queue_depth(), 2);
+
+        assert_eq!(handle.flush().await, 2);
+        assert_eq!(handle.queue_depth(), 0);
+        assert_eq!(shipped(&batches).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn shipped_and_failed_logs_are_counted() {
+        for (status, shipped, failed) in
+            [(StatusCode::ACCEPTED, 2, 0), (StatusCode::FORBIDDEN, 0, 2)]
+        {
+            let (url, _batches) = mock_intake(match status {
+                StatusCode::ACCEPTED => |_| StatusCode::ACCEPTED,
+                _ => |_| StatusCode::FORBIDDEN,
+            });
+            let mut handle = DatadogHandle::default();
+            let layer = handle.layer(Ingestor::new(&url, "api-key", "test", ""));
+            let guard = tracing::subscriber::set_default(subscriber(layer));
+            tracing::info!("one");
+            tracing::info!("two");
+
+            handle.flush().await;
+            drop(guard);
+
+            assert_eq!(handle.shipped(), shipped, "{status}");
+            assert_eq!(handle.failed(), failed, "{status}");
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use crate::{capture::SENSITIVE_HEADERS, state::SharedState};
+use axum::{
+    extract::State,
+    http::{HeaderName, Request},
+    middleware::Next,
+    response::Response,
+};
+
+/// Copies the request headers in `DEBUG_ECHO_HEADERS` to the response as `X-Echo-<header>`.
+///
+/// Sensitive headers are never echoed, even if they're in the allowlist.
+pub async fn echo_headers<B>(
+    State(state): State<SharedState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let echoed = state
+        .config()
+        .debug_echo_headers
+        .iter()
+        .filter(|name| !SENSITIVE_HEADERS.contains(&name.as_str()))
+        .filter_map(|name| {
+            let echo_name = HeaderName::try_from(format!("x-echo-{name}")).ok()?;
+            let values = req.headers().get_all(name.as_str()).iter().cloned();
+            Some((echo_name, values.collect::<Vec<_>>()))
+        })
+        .collect::<Vec<_>>();
+
+    let mut response = next.run(req).await;
+    let headers = response.headers_mut();
+    for (name, values) in echoed {
+        for value in values {
+            headers.append(name.clone(), value);
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{self, send};
+    use axum::{body::Body, http::Request};
+
+    #[tokio::test]
+    async fn allowed_headers_are_echoed_but_never_sensitive_ones() {
+        let test = test_support::state(&[("DEBUG_ECHO_HEADERS", "X-Tenant,Authorization")]);
+        let req = Request::get("/")
+            .header("x-tenant", "acme")
+            .header("authorization", "Bearer secret")
+            .header("x-other", "other")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = send(test.router(), req).await;
+
+        let echoed = response
+            .headers()
+            .keys()
+            .filter(|name| name.as_str().starts_with("x-echo-"))
+            .collect::<Vec<_>>();
+        assert_eq!(echoed, ["x-echo-x-tenant"]);
+        assert_eq!(response.headers()["x-echo-x-tenant"], "acme");
+    }
+
+    #[tokio::test]
+    async fn nothing_is_echoed_by_default() {
+        let test = test_support::state(&[]);
+        let req = Request::get("/")
+            .header("x-tenant", "acme")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = send(test.router(), req).await;
+
+        assert!(!response.headers().contains_key("x-echo-x-tenant"));
+    }
+}
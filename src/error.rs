@@ -0,0 +1,175 @@
+use crate::state::SharedState;
+use axum::{
+    extract::State,
+    http::{header, HeaderValue, Request, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+
+#[derive(Debug)]
+pub enum AppError {
+    BadRequest(String),
+    Unauthorized,
+    Forbidden,
+    TooManyRequests,
+    Timeout,
+    UriTooLong,
+    Config(anyhow::Error),
+    Datadog(reqwest::Error),
+}
+
+impl AppError {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Unauthorized => StatusCode::UNAUTHORIZED,
+            Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            Self::Timeout => StatusCode::REQUEST_TIMEOUT,
+            Self::UriTooLong => StatusCode::URI_TOO_LONG,
+            Self::Config(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Datadog(_) => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::BadRequest(message) => write!(f, "{message}"),
+            Self::Unauthorized => write!(f, "unauthorized"),
+            Self::Forbidden => write!(f, "forbidden"),
+            Self::TooManyRequests => write!(f, "too many concurrent requests"),
+            Self::Timeout => write!(f, "request timed out"),
+            Self::UriTooLong => write!(f, "URI too long"),
+            Self::Config(e) => write!(f, "invalid configuration: {e:#}"),
+            Self::Datadog(e) => write!(f, "Datadog request failed: {e}"),
+        }
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let status = self.status();
+        if status.is_server_error() {
+            tracing::error!(error = %self, "request failed");
+        }
+        let detail = self.to_string();
+        let mut response = (status, Json(json!({ "error": detail }))).into_response();
+        // the config isn't reachable from here, `problem_json` decides the final shape
+        response.extensions_mut().insert(ErrorDetail(detail));
+        response
+    }
+}
+
+/// Message of an [`AppError`] response.
+#[derive(Debug, Clone)]
+struct ErrorDetail(String);
+
+/// Rewrites the [`AppError`] responses as RFC 7807 problem details when `PROBLEM_JSON` is
+/// enabled.
+pub async fn problem_json<B>(
+    State(state): State<SharedState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let instance = req.uri().path().to_owned();
+    let response = next.run(req).await;
+    if !state.config().problem_json {
+        return response;
+    }
+    let Some(ErrorDetail(detail)) = response.extensions().get::<ErrorDetail>().cloned() else {
+        return response;
+    };
+
+    let (mut parts, _) = response.into_parts();
+    let status = parts.status;
+    let body = json!({
+        "type": "about:blank",
+        "title": status.canonical_reason().unwrap_or("Unknown Error"),
+        "status": status.as_u16(),
+        "detail": detail,
+        "instance": instance,
+    });
+    parts.headers.insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/problem+json"),
+    );
+    // the body is replaced, and it may have been compressed
+    parts.headers.remove(header::CONTENT_LENGTH);
+    parts.headers.remove(header::CONTENT_ENCODING);
+    (parts, Json(body)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, body_string, send};
+    use axum::{body::Body, routing::get, Router};
+    use serde_json::Value;
+
+    /// Answers `uri` with an app that fails with the [`AppError`]s.
+    async fn error_response(pairs: &[(&str, &str)], uri: &str) -> (StatusCode, String, Value) {
+        let test = test_support::state(pairs);
+        let router = Router::new()
+            .route(
+                "/busy",
+                get(|| async { Err::<(), _>(AppError::TooManyRequests) }),
+            )
+            .route(
+                "/unauthorized",
+                get(|| async { Err::<(), _>(AppError::Unauthorized) }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                test.state.clone(),
+                problem_json,
+            ));
+        let req = Request::get(uri).body(Body::empty()).unwrap();
+
+        let response = send(router, req).await;
+
+        let status = response.status();
+        let content_type = response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_string();
+        let body = serde_json::from_str(&body_string(response).await).unwrap();
+        (status, content_type, body)
+    }
+
+    #[tokio::test]
+    async fn errors_have_the_simple_shape_by_default() {
+        let (status, content_type, body) = error_response(&[], "/busy").await;
+
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(content_type, "application/json");
+        assert_eq!(body, json!({ "error": "too many concurrent requests" }));
+    }
+
+    #[tokio::test]
+    async fn errors_are_problem_details_with_problem_json() {
+        let pairs = [("PROBLEM_JSON", "true")];
+
+        let (status, content_type, body) = error_response(&pairs, "/busy").await;
+        assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(content_type, "application/problem+json");
+        assert_eq!(
+            body,
+            json!({
+                "type": "about:blank",
+                "title": "Too Many Requests",
+                "status": 429,
+                "detail": "too many concurrent requests",
+                "instance": "/busy",
+            })
+        );
+
+        let (status, _, body) = error_response(&pairs, "/unauthorized").await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["title"], "Unauthorized");
+        assert_eq!(body["status"], 401);
+        assert_eq!(body["detail"], "unauthorized");
+    }
+}
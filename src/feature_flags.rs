@@ -0,0 +1,115 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{request::Parts, Request},
+    middleware::Next,
+    response::Response,
+};
+use std::{collections::BTreeSet, convert::Infallible};
+
+pub const FEATURE_FLAGS_HEADER: &str = "x-feature-flags";
+
+/// More flags than this are ignored, they end up in every log of the request.
+const MAX_FLAGS: usize = 32;
+
+/// Flags of the request, from the comma-separated `X-Feature-Flags` header.
+///
+/// Handlers get them as an extractor. Flags are lowercased.
+#[derive(Debug, Clone, Default)]
+pub struct FeatureFlags(BTreeSet<String>);
+
+impl FeatureFlags {
+    pub fn parse(header: &str) -> Self {
+        let flags = header
+            .split(',')
+            .map(|flag| flag.trim().to_lowercase())
+            .filter(|flag| !flag.is_empty())
+            .take(MAX_FLAGS)
+            .collect();
+        Self(flags)
+    }
+
+    pub fn is_enabled(&self, name: &str) -> bool {
+        self.0.contains(&name.to_lowercase())
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for FeatureFlags {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(parts
+            .extensions
+            .get::<FeatureFlags>()
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+/// Parses the `X-Feature-Flags` header into the [`FeatureFlags`] of the request, and records
+/// them in the `feature_flags` field of the request span.
+pub async fn record_feature_flags<B>(mut req: Request<B>, next: Next<B>) -> Response {
+    let flags = req
+        .headers()
+        .get(FEATURE_FLAGS_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(FeatureFlags::parse)
+        .unwrap_or_default();
+    if !flags.0.is_empty() {
+        let recorded = flags.0.iter().cloned().collect::<Vec<_>>().join(",");
+        tracing::Span::current().record("feature_flags", recorded);
+    }
+    req.extensions_mut().insert(flags);
+    next.run(req).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, body_string, send, Logs};
+    use axum::body::Body;
+
+    #[test]
+    fn flags_are_trimmed_lowercased_and_capped() {
+        let flags = FeatureFlags::parse(" Shout, ,new-ui ");
+        assert!(flags.is_enabled("shout"));
+        assert!(flags.is_enabled("NEW-UI"));
+        assert!(!flags.is_enabled("beta"));
+
+        let many = (0..100).map(|i| format!("f{i}")).collect::<Vec<_>>();
+        assert_eq!(FeatureFlags::parse(&many.join(",")).0.len(), MAX_FLAGS);
+    }
+
+    #[tokio::test]
+    async fn handlers_see_the_flags_and_they_are_logged() {
+        let test = test_support::state(&[]);
+        let (logs, _guard) = Logs::capture();
+
+        let req = Request::get("/")
+            .header(FEATURE_FLAGS_HEADER, "shout,beta")
+            .body(Body::empty())
+            .unwrap();
+        let response = send(test.router(), req).await;
+
+        assert_eq!(body_string(response).await, "HELLO, WORLD!");
+        let completed = logs.find("request completed").expect("no completion log");
+        assert_eq!(completed["span"]["feature_flags"], "beta,shout");
+    }
+
+    #[tokio::test]
+    async fn no_flags_by_default() {
+        let test = test_support::state(&[]);
+        let (logs, _guard) = Logs::capture();
+
+        let response = send(
+            test.router(),
+            Request::get("/").body(Body::empty()).unwrap(),
+        )
+        .await;
+
+        assert_eq!(body_string(response).await, "Hello, world!");
+        let completed = logs.find("request completed").expect("no completion log");
+        assert!(completed["span"].get("feature_flags").is_none());
+    }
+}
@@ -0,0 +1,282 @@
+use crate::{datadog, state::SharedState};
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde_json::json;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+const READINESS_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long a readiness check result is reused, probes can be very frequent.
+const READINESS_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Body of the liveness check. Some monitoring systems want the literal `OK`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HealthBody {
+    Ok,
+    Json,
+}
+
+impl HealthBody {
+    pub fn parse(body: &str) -> anyhow::Result<Self> {
+        match body.trim().to_lowercase().as_str() {
+            "ok" => Ok(Self::Ok),
+            "json" => Ok(Self::Json),
+            _ => anyhow::bail!("invalid HEALTH_BODY: {body}"),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Json => "json",
+        }
+    }
+}
+
+/// Liveness check. Always `200`. `HEAD` is answered too, with the same headers and no body.
+pub async fn health(State(state): State<SharedState>) -> Response {
+    match state.config().health_body {
+        HealthBody::Ok => "OK".into_response(),
+        HealthBody::Json => Json(json!({ "status": "ok" })).into_response(),
+    }
+}
+
+/// Dependency that can gate `/ready`, selected with `READINESS_CHECKS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ReadinessCheck {
+    /// Datadog accepts the API key, i.e. logs are being shipped.
+    Datadog,
+}
+
+impl ReadinessCheck {
+    pub fn parse(check: &str) -> anyhow::Result<Self> {
+        match check.trim().to_lowercase().as_str() {
+            "datadog" => Ok(Self::Datadog),
+            _ => anyhow::bail!("unknown readiness check: {check}"),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Datadog => "datadog",
+        }
+    }
+
+    /// `None` when it couldn't tell, e.g. Datadog timed out.
+    async fn run(&self, state: &SharedState) -> Option<bool> {
+        match self {
+            Self::Datadog => {
+                let config = state.config();
+                let validation = datadog::validate_api_key(
+                    &state.http_client,
+                    &config.dd_api_url,
+                    &config.dd_api_key,
+                );
+                match tokio::time::timeout(READINESS_TIMEOUT, validation).await {
+                    Ok(Ok(valid)) => Some(valid),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+/// Last result of each readiness check, reused for [`READINESS_CACHE_TTL`].
+#[derive(Debug, Default)]
+pub struct ReadinessCache {
+    results: Mutex<HashMap<ReadinessCheck, (bool, Instant)>>,
+}
+
+impl ReadinessCache {
+    /// Result of `check`, running it if the cached one expired.
+    ///
+    /// If the check can't tell, the previous result is kept so a hiccup of the dependency
+    /// doesn't take the instance out of rotation (there's none the first time, it fails).
+    async fn get(&self, check: ReadinessCheck, state: &SharedState) -> bool {
+        let cached = self.lock().get(&check).copied();
+        if let Some((ok, checked_at)) = cached {
+            if checked_at.elapsed() < READINESS_CACHE_TTL {
+                return ok;
+            }
+        }
+        let ok = match check.run(state).await {
+            Some(ok) => ok,
+            None => {
+                tracing::warn!(check = check.as_str(), "Readiness check inconclusive");
+                cached.is_some_and(|(ok, _)| ok)
+            }
+        };
+        self.lock().insert(check, (ok, Instant::now()));
+        ok
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<ReadinessCheck, (bool, Instant)>> {
+        self.results.lock().expect("readiness cache lock poisoned")
+    }
+}
+
+/// Readiness check. `503` once the instance is draining, so the load balancer stops sending
+/// new traffic while the in-flight requests finish, or when one of the `READINESS_CHECKS`
+/// fails.
+pub async fn ready(State(state): State<SharedState>) -> Response {
+    if state.is_draining() {
+        let body = Json(json!({ "status": "draining" }));
+        return (StatusCode::SERVICE_UNAVAILABLE, body).into_response();
+    }
+
+    let mut checks = serde_json::Map::new();
+    for check in state.config().readiness_checks {
+        let ok = state.readiness.get(check, &state).await;
+        if !ok {
+            tracing::warn!(check = check.as_str(), "Readiness check failed");
+        }
+        checks.insert(
+            check.as_str().into(),
+            json!(if ok { "ok" } else { "failed" }),
+        );
+    }
+
+    if checks.values().all(|result| result == "ok") {
+        Json(json!({ "status": "ready", "checks": checks })).into_response()
+    } else {
+        let body = Json(json!({ "status": "not_ready", "checks": checks }));
+        (StatusCode::SERVICE_UNAVAILABLE, body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, body_string, send};
+    use axum::{
+        body::Body,
+        http::{header, Method, Request, StatusCode},
+    };
+    use serde_json::Value;
+
+    fn request(method: Method, uri: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn head_health_has_the_headers_but_no_body() {
+        let test = test_support::state(&[]);
+
+        let response = send(test.router(), request(Method::HEAD, "/health")).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/json");
+        assert_eq!(body_string(response).await, "");
+    }
+
+    #[tokio::test]
+    async fn other_methods_are_not_allowed() {
+        let test = test_support::state(&[]);
+
+        let response = send(test.router(), request(Method::DELETE, "/health")).await;
+
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[header::ALLOW], "GET,HEAD");
+    }
+
+    #[tokio::test]
+    async fn health_body_is_configurable() {
+        for (health_body, content_type, body) in [
+            (None, "application/json", r#"{"status":"ok"}"#),
+            (Some("json"), "application/json", r#"{"status":"ok"}"#),
+            (Some("ok"), "text/plain; charset=utf-8", "OK"),
+        ] {
+            let pairs = health_body.map(|health_body| ("HEALTH_BODY", health_body));
+            let test = test_support::state(pairs.as_slice());
+
+            let response = send(test.router(), request(Method::GET, "/health")).await;
+
+            assert_eq!(response.status(), StatusCode::OK);
+            assert_eq!(response.headers()[header::CONTENT_TYPE], content_type);
+            assert_eq!(body_string(response).await, body);
+        }
+    }
+
+    #[test]
+    fn invalid_health_body_is_rejected() {
+        assert!(HealthBody::parse("xml").is_err());
+        assert_eq!(HealthBody::parse(" OK ").unwrap(), HealthBody::Ok);
+    }
+
+    #[tokio::test]
+    async fn ready_fails_once_draining_but_health_does_not() {
+        let test = test_support::state(&[]);
+        let ready = || send(test.router(), request(Method::GET, "/ready"));
+        assert_eq!(ready().await.status(), StatusCode::OK);
+
+        assert!(!test.state.drain());
+
+        let response = ready().await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body_string(response).await, r#"{"status":"draining"}"#);
+        let response = send(test.router(), request(Method::GET, "/health")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    /// `/ready` with `DD_API_KEY` checked against a fake Datadog.
+    async fn ready_with(api_key: &str, checks: &str) -> (StatusCode, Value) {
+        let dd_api_url = test_support::mock_datadog();
+        let test = test_support::state(&[
+            ("DD_API_KEY", api_key),
+            ("DD_API_URL", &dd_api_url),
+            ("READINESS_CHECKS", checks),
+        ]);
+        let response = send(test.router(), request(Method::GET, "/ready")).await;
+        let status = response.status();
+        (
+            status,
+            serde_json::from_str(&body_string(response).await).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn only_the_selected_checks_gate_readiness() {
+        let (status, body) = ready_with("wrong-key", "").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, json!({ "status": "ready", "checks": {} }));
+
+        let (status, body) = ready_with("wrong-key", "datadog").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            body,
+            json!({ "status": "not_ready", "checks": { "datadog": "failed" } })
+        );
+
+        let (status, body) = ready_with("valid-key", "datadog").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["checks"]["datadog"], "ok");
+    }
+
+    #[tokio::test]
+    async fn inconclusive_first_check_fails() {
+        let (status, body) = ready_with("broken-key", "datadog").await;
+
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["checks"]["datadog"], "failed");
+    }
+
+    #[test]
+    fn unknown_readiness_checks_are_rejected() {
+        assert_eq!(
+            ReadinessCheck::parse(" Datadog ").unwrap(),
+            ReadinessCheck::Datadog
+        );
+        assert!(ReadinessCheck::parse("db").is_err());
+    }
+}
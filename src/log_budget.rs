@@ -0,0 +1,118 @@
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tracing::{Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+const WINDOW: Duration = Duration::from_secs(60);
+/// Target of the warning, so it doesn't count against the budget itself. It's local only, the
+/// Datadog layers drop it.
+pub const TARGET: &str = "log_budget";
+
+/// Counts the events it sees per minute and warns once per minute when they go over `budget`.
+///
+/// It's advisory only: nothing is dropped.
+pub struct LogBudget {
+    budget: u64,
+    window: Mutex<Window>,
+}
+
+struct Window {
+    started_at: Instant,
+    count: u64,
+    warned: bool,
+}
+
+impl LogBudget {
+    pub fn new(budget: u64) -> Self {
+        Self {
+            budget,
+            window: Mutex::new(Window {
+                started_at: Instant::now(),
+                count: 0,
+                warned: false,
+            }),
+        }
+    }
+
+    /// Records an event, returning the count if this is the one crossing the budget.
+    fn record(&self) -> Option<u64> {
+        let mut window = self.window.lock().unwrap_or_else(|e| e.into_inner());
+        if window.started_at.elapsed() >= WINDOW {
+            *window = Window {
+                started_at: Instant::now(),
+                count: 0,
+                warned: false,
+            };
+        }
+        window.count += 1;
+        if window.count > self.budget && !window.warned {
+            window.warned = true;
+            Some(window.count)
+        } else {
+            None
+        }
+    }
+}
+
+impl<S: Subscriber> Layer<S> for LogBudget {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if event.metadata().target() == TARGET {
+            return;
+        }
+        // the lock is released by now, the warning goes through this layer again
+        if let Some(count) = self.record() {
+            tracing::warn!(target: TARGET, count, budget = self.budget, "log budget exceeded");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use tracing_subscriber::{prelude::*, Registry};
+
+    /// Shared with the subscriber, so the test can look at its window.
+    struct Shared(Arc<LogBudget>);
+
+    impl<S: Subscriber> Layer<S> for Shared {
+        fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+            self.0.on_event(event, ctx);
+        }
+    }
+
+    // the warning itself can't be captured here: tracing drops the events emitted while
+    // dispatching another one to a scoped (test) subscriber
+    #[test]
+    fn warns_once_per_window() {
+        let budget = LogBudget::new(3);
+        let crossings = |budget: &LogBudget, events| {
+            (0..events)
+                .filter_map(|_| budget.record())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(crossings(&budget, 10), [4]);
+        assert!(crossings(&budget, 10).is_empty());
+
+        // a minute later
+        budget.window.lock().unwrap().started_at -= WINDOW;
+        assert!(crossings(&budget, 3).is_empty());
+        assert_eq!(crossings(&budget, 1), [4]);
+    }
+
+    #[test]
+    fn its_own_warnings_are_not_counted() {
+        let budget = Arc::new(LogBudget::new(3));
+        let subscriber = Registry::default().with(Shared(budget.clone()));
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("hello");
+            tracing::warn!(target: TARGET, "log budget exceeded");
+        });
+
+        assert_eq!(budget.window.lock().unwrap().count, 1);
+    }
+}
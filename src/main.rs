@@ -1,70 +1,327 @@
-use axum::{routing::get, Router};
-use dd_tracing_layer::{DatadogOptions, Region};
+use axum::{
+    http::{HeaderName, HeaderValue},
+    routing::{get, MethodRouter},
+    Router,
+};
+use config::Config;
+use feature_flags::FeatureFlags;
+use secrets::Secrets;
+use service::{AppService, StartupLogStyle};
 use shuttle_secrets::SecretStore;
+use state::{AppState, SharedState};
+use std::time::Instant;
+use tower_http::set_header::SetResponseHeaderLayer;
 use tracing::instrument;
-use tracing_subscriber::prelude::*;
 
-const VERSION: &'static str = "version:0.1.0";
+mod admin;
+mod build_info;
+mod capture;
+mod client;
+mod cloudevents;
+mod compression;
+mod config;
+mod datadog;
+mod echo;
+mod error;
+mod feature_flags;
+mod health;
+mod log_budget;
+mod metrics;
+mod middleware;
+mod openapi;
+mod query;
+mod request_id;
+mod sampling;
+mod secrets;
+mod service;
+mod signature;
+mod span_filter;
+mod span_limit;
+mod state;
+mod telemetry;
+#[cfg(test)]
+mod test_support;
+mod trace_context;
+mod well_known;
 
-#[instrument]
-async fn hello_world() -> &'static str {
+#[instrument(skip(flags))]
+async fn hello_world(flags: FeatureFlags) -> &'static str {
     tracing::info!("Saying hello");
     tracing::debug!("Saying hello for debug level only");
+    if flags.is_enabled("shout") {
+        return "HELLO, WORLD!";
+    }
     "Hello, world!"
 }
 
 #[shuttle_runtime::main]
-async fn axum(#[shuttle_secrets::Secrets] secret_store: SecretStore) -> shuttle_axum::ShuttleAxum {
-    // getting the Datadog Key from the secrets
-    let dd_api_key = secret_store
-        .get("DD_API_KEY")
-        .expect("DD_API_KEY not found");
-
-    // getting the Datadog tags from the secrets
-    let tags = secret_store
-        .get("DD_TAGS")
-        .map(|tags| format!("{},{}", tags, VERSION))
-        .unwrap_or(VERSION.to_string());
-
-    // getting the log level from the secrets
-    let log_level = secret_store.get("LOG_LEVEL").unwrap_or("INFO".to_string());
-
-    // datadog tracing layer
-    let dd_layer = dd_tracing_layer::create(
-        DatadogOptions::new(
-            // first parameter is the name of the service
-            "shuttle-datadog-logs",
-            // this is the Datadog API Key
-            dd_api_key,
-        )
-        // this is the default, so it can be omitted
-        .with_region(Region::US1)
-        // adding some optional tags
-        .with_tags(tags),
-    );
-
-    // filter layer
-    let filter_layer =
-        tracing_subscriber::EnvFilter::try_new(log_level).expect("failed to set log level");
-
-    // format layer
-    let fmt_layer = tracing_subscriber::fmt::layer()
-        .with_ansi(true)
-        .with_timer(tracing_subscriber::fmt::time::UtcTime::rfc_3339())
-        .json()
-        .flatten_event(true)
-        .with_target(true)
-        .with_span_list(true);
-
-    // starting the tracing subscriber
-    tracing_subscriber::registry()
-        .with(filter_layer)
-        .with(fmt_layer)
-        .with(dd_layer)
-        .init();
+async fn axum(
+    #[shuttle_secrets::Secrets] secret_store: SecretStore,
+) -> Result<AppService, shuttle_runtime::Error> {
+    // taken before anything else to measure the cold start
+    let started_at = Instant::now();
+
+    let secrets = Secrets::new(secret_store);
+    let config = Config::from_secrets(&secrets)?;
+    if config.dump_env {
+        print!("{}", config.to_env());
+    }
+
+    let (log_reload, datadog) = telemetry::init(&config);
+    secrets.log_pending();
+    if config.max_concurrent_per_client > 0 && !config.trust_proxy {
+        // the clients are told apart by the peer address, i.e. the proxy's when there's one
+        tracing::warn!(
+            max_concurrent_per_client = config.max_concurrent_per_client,
+            "MAX_CONCURRENT_PER_CLIENT is set without TRUST_PROXY, all the clients behind a proxy share the limit"
+        );
+    }
+
+    let admin_enabled = config.admin_token.is_some();
+    // `summary` logs all of it once the server is bound
+    let verbose = config.startup_log_style == StartupLogStyle::Verbose;
+    let state = AppState::new(config, secrets, log_reload, datadog, started_at);
 
     // starting the server
-    let router = Router::new().route("/", get(hello_world));
-    tracing::info!("Starting axum service");
-    Ok(router.into())
+    let router = build_router(state.clone());
+    let admin_router = build_admin_router(state.clone());
+    if cfg!(debug_assertions) {
+        // debug builds are way slower, it shouldn't be deployed by accident
+        tracing::warn!(profile = build_info::PROFILE, "running debug build");
+    } else if verbose {
+        tracing::info!(profile = build_info::PROFILE, "release build");
+    }
+    if verbose {
+        tracing::info!(
+            admin_enabled,
+            profile = build_info::PROFILE,
+            build_time = build_info::BUILD_TIMESTAMP,
+            git_branch = build_info::GIT_BRANCH,
+            worker_threads = service::worker_threads(),
+            "Starting axum service"
+        );
+    }
+    Ok(AppService::new(router, admin_router, state, started_at))
+}
+
+/// Public routes, plus `/admin` unless `ADMIN_PORT` gives it its own listener.
+fn build_router(state: SharedState) -> Router {
+    let config = state.config();
+
+    let routes: [(&str, MethodRouter<SharedState>); 7] = [
+        ("/", get(hello_world)),
+        ("/health", get(health::health)),
+        ("/metrics", get(metrics::metrics)),
+        ("/ready", get(health::ready)),
+        ("/robots.txt", get(well_known::robots_txt)),
+        ("/.well-known/security.txt", get(well_known::security_txt)),
+        ("/openapi.json", get(openapi::openapi)),
+    ];
+    let is_enabled = |path: &str| !config.disabled_routes.iter().any(|route| route == path);
+
+    let mut router = Router::new();
+    for (path, handler) in routes.into_iter().filter(|(path, _)| is_enabled(path)) {
+        router = router.route(path, handler);
+    }
+    if config.admin_port.is_none() && config.admin_token.is_some() && is_enabled("/admin") {
+        router = router.nest("/admin", admin::router(state.clone()));
+    }
+    with_layers(router, state)
+}
+
+/// `/admin` alone, served on `ADMIN_PORT` when it's set.
+fn build_admin_router(state: SharedState) -> Option<Router> {
+    let config = state.config();
+    let disabled = config.disabled_routes.iter().any(|route| route == "/admin");
+    if config.admin_port.is_none() || config.admin_token.is_none() || disabled {
+        return None;
+    }
+    let router = Router::new().nest("/admin", admin::router(state.clone()));
+    Some(with_layers(router, state))
+}
+
+fn with_layers(mut router: Router<SharedState>, state: SharedState) -> Router {
+    let config = state.config();
+
+    // signing what the client gets once it's decompressed
+    if config.response_signing_key.is_some() {
+        router = router.layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            signature::sign_responses,
+        ));
+    }
+    router = router.layer(compression::layer(config.compress_min_bytes));
+    if config.expose_deployment_headers {
+        let region = format!("{:?}", config.region);
+        router = router.layer(SetResponseHeaderLayer::overriding(
+            HeaderName::from_static("x-region"),
+            HeaderValue::from_str(&region).expect("region is a valid header value"),
+        ));
+        match config.dd_env.as_deref().map(HeaderValue::from_str) {
+            Some(Ok(env)) => {
+                router = router.layer(SetResponseHeaderLayer::overriding(
+                    HeaderName::from_static("x-env"),
+                    env,
+                ));
+            }
+            Some(Err(_)) => tracing::warn!("DD_ENV is not a valid header value, X-Env not set"),
+            None => {}
+        }
+    }
+    if config.canary {
+        router = router.layer(SetResponseHeaderLayer::overriding(
+            HeaderName::from_static("x-canary"),
+            HeaderValue::from_static("true"),
+        ));
+    }
+    if state.captures.is_enabled() {
+        router = router.layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            capture::capture_requests,
+        ));
+    }
+    if !config.debug_echo_headers.is_empty() {
+        router = router.layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            echo::echo_headers,
+        ));
+    }
+    if config.max_concurrent_per_client > 0 {
+        router = router.layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            client::limit_per_client,
+        ));
+    }
+    if config.log_query {
+        router = router.layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            query::record_query,
+        ));
+    }
+    router = router.layer(axum::middleware::from_fn(
+        feature_flags::record_feature_flags,
+    ));
+    router = router.layer(axum::middleware::from_fn_with_state(
+        state.clone(),
+        middleware::limit_duration,
+    ));
+    router = router.layer(axum::middleware::from_fn_with_state(
+        state.clone(),
+        middleware::require_header,
+    ));
+    router = router.layer(axum::middleware::from_fn_with_state(
+        state.clone(),
+        middleware::record_queue_time,
+    ));
+    if config.max_uri_len > 0 {
+        router = router.layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::limit_uri_len,
+        ));
+    }
+    // outside of the other middlewares so their errors are rewritten too
+    router = router.layer(axum::middleware::from_fn_with_state(
+        state.clone(),
+        error::problem_json,
+    ));
+    router = router.layer(axum::middleware::from_fn_with_state(
+        state.clone(),
+        middleware::trace_requests,
+    ));
+    router.with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{self, send};
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+    };
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn other_methods_are_not_allowed() {
+        let test = test_support::state(&[]);
+
+        let req = Request::delete("/").body(Body::empty()).unwrap();
+        let response = send(test.router(), req).await;
+
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[header::ALLOW], "GET,HEAD");
+    }
+
+    #[tokio::test]
+    async fn deployment_headers_are_exposed_when_enabled() {
+        let test = test_support::state(&[
+            ("EXPOSE_DEPLOYMENT_HEADERS", "true"),
+            ("DD_REGION", "EU"),
+            ("DD_ENV", "staging"),
+        ]);
+
+        let response = send(test.router(), get("/")).await;
+
+        assert_eq!(response.headers()["x-region"], "EU");
+        assert_eq!(response.headers()["x-env"], "staging");
+    }
+
+    #[tokio::test]
+    async fn deployment_headers_are_not_exposed_by_default() {
+        let test = test_support::state(&[("DD_REGION", "EU"), ("DD_ENV", "staging")]);
+
+        let response = send(test.router(), get("/")).await;
+
+        assert!(!response.headers().contains_key("x-region"));
+        assert!(!response.headers().contains_key("x-env"));
+    }
+
+    #[tokio::test]
+    async fn disabled_routes_are_not_found() {
+        let test = test_support::state(&[
+            ("DISABLED_ROUTES", "/robots.txt,/admin"),
+            ("ADMIN_TOKEN", "admin-token"),
+        ]);
+
+        for (uri, status) in [
+            ("/robots.txt", StatusCode::NOT_FOUND),
+            ("/admin/captures", StatusCode::NOT_FOUND),
+            ("/", StatusCode::OK),
+            ("/health", StatusCode::OK),
+        ] {
+            let mut req = get(uri);
+            req.headers_mut()
+                .insert("authorization", "Bearer admin-token".parse().unwrap());
+            let response = send(test.router(), req).await;
+            assert_eq!(response.status(), status, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn canary_deployments_are_tagged_and_marked() {
+        for (canary, marked) in [(Some("true"), true), (Some("false"), false), (None, false)] {
+            let pairs = canary.map(|canary| ("CANARY", canary));
+            let test = test_support::state(pairs.as_slice());
+
+            let response = send(test.router(), get("/")).await;
+
+            let tagged = test
+                .state
+                .config()
+                .tags
+                .split(',')
+                .any(|tag| tag == "canary:true");
+            assert_eq!(tagged, marked, "{canary:?}");
+            assert_eq!(
+                response
+                    .headers()
+                    .get("x-canary")
+                    .map(|value| value.as_bytes()),
+                marked.then_some(b"true".as_slice()),
+                "{canary:?}"
+            );
+        }
+    }
 }
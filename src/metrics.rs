@@ -0,0 +1,33 @@
+use crate::state::SharedState;
+use axum::{extract::State, Json};
+use serde_json::{json, Value};
+
+/// `/metrics`, how the shipping of the logs to Datadog is going.
+pub async fn metrics(State(state): State<SharedState>) -> Json<Value> {
+    Json(json!({
+        "dd.queue.depth": state.datadog.queue_depth(),
+        "dd.logs.shipped": state.datadog.shipped(),
+        "dd.logs.failed": state.datadog.failed(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{self, body_string, send};
+    use axum::{body::Body, http::Request};
+    use serde_json::{json, Value};
+
+    #[tokio::test]
+    async fn metrics_has_the_datadog_counters() {
+        let test = test_support::state(&[]);
+
+        let req = Request::get("/metrics").body(Body::empty()).unwrap();
+        let body = body_string(send(test.router(), req).await).await;
+
+        let metrics: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            metrics,
+            json!({ "dd.queue.depth": 0, "dd.logs.shipped": 0, "dd.logs.failed": 0 })
+        );
+    }
+}
@@ -0,0 +1,719 @@
+use crate::{
+    capture,
+    error::AppError,
+    request_id::REQUEST_ID_HEADER,
+    sampling,
+    service::elapsed_ms,
+    signature,
+    state::SharedState,
+    trace_context::{TraceParent, TRACEPARENT},
+};
+use axum::{
+    extract::{MatchedPath, State},
+    http::{header, HeaderName, HeaderValue, Method, Request, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::{field, Instrument};
+
+/// How much of an over-long URI is logged.
+const LOGGED_URI_LEN: usize = 128;
+
+/// How much of the tenant id is logged.
+const TENANT_ID_LEN: usize = 64;
+
+/// Routes whose completion logs are sampled with `HEALTH_LOG_SAMPLE`.
+const HEALTH_ROUTES: &[&str] = &["/health", "/ready"];
+
+/// Team owning each route, added as the `owner` field so alerts can be routed.
+const ROUTE_OWNERS: &[(&str, &str)] = &[("/", "platform"), ("/health", "sre")];
+/// Owner of the routes missing from [`ROUTE_OWNERS`].
+const DEFAULT_OWNER: &str = "platform";
+
+/// Wraps every request in a `request` span carrying the HTTP semantic fields, and logs its
+/// completion.
+///
+/// The response gets `traceparent` and `X-Request-Id` headers so callers can correlate it
+/// with our logs.
+pub async fn trace_requests<B>(
+    State(state): State<SharedState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let config = state.config();
+    let trace_parent = TraceParent::for_request(req.headers());
+    let request_id = config.request_id_format.generate();
+    // child spans and events inherit it, so every log of the request carries it
+    let tenant_id = config
+        .tenant_header
+        .and_then(|header| req.headers().get(header.as_str()).cloned())
+        .map(|value| truncate(&capture::sanitize_header_value(&value), TENANT_ID_LEN).to_owned());
+    // the over-long ones are rejected by `limit_uri_len`, but shouldn't end up in the logs
+    let path = req.uri().path();
+    let path = if config.max_uri_len > 0 && req.uri().to_string().len() > config.max_uri_len {
+        truncate(path, LOGGED_URI_LEN)
+    } else {
+        path
+    };
+    let route = req
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned());
+    let span = tracing::info_span!(
+        "request",
+        http.method = %req.method(),
+        http.url = path,
+        http.query = field::Empty,
+        queue_time_ms = field::Empty,
+        http.route = route,
+        owner = owner(route.as_deref()),
+        trace_id = trace_parent.trace_id_hex(),
+        request_id,
+        host = config.pod_name,
+        tenant_id,
+        feature_flags = field::Empty,
+        http.status_code = field::Empty,
+        error_class = field::Empty,
+    );
+
+    let start = Instant::now();
+    let mut response = next.run(req).instrument(span.clone()).await;
+    response
+        .headers_mut()
+        .insert(TRACEPARENT, trace_parent.to_header_value());
+    if let Ok(request_id) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, request_id);
+    }
+
+    let status = response.status();
+    let error_class = error_class(status);
+    if status.is_client_error() {
+        state.client_errors.add();
+    } else if status.is_server_error() {
+        state.server_errors.add();
+    }
+    span.record("http.status_code", status.as_u16());
+    span.record("error_class", error_class);
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .map(|value| capture::sanitize_header_value(value).into_owned());
+    let logged = match route.as_deref() {
+        Some(route) if HEALTH_ROUTES.contains(&route) => {
+            sampling::sample(&state.health_checks, config.health_log_sample)
+        }
+        _ => true,
+    };
+    if logged {
+        span.in_scope(|| {
+            tracing::info!(
+                http.status_code = status.as_u16(),
+                duration_ms = elapsed_ms(start),
+                error_class,
+                status = dd_status(status),
+                response.content_type = content_type,
+                "request completed"
+            );
+        });
+    }
+
+    response
+}
+
+/// Records how long the request waited in the load balancer as `queue_time_ms`, from the
+/// `X-Request-Start` header it set. Only when `TRUST_PROXY` is enabled.
+pub async fn record_queue_time<B>(
+    State(state): State<SharedState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if state.config().trust_proxy {
+        let request_start = req
+            .headers()
+            .get("x-request-start")
+            .and_then(|value| value.to_str().ok())
+            .and_then(parse_request_start);
+        if let Some(request_start) = request_start {
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default();
+            // clocks may be skewed, it can't be negative
+            let queue_time_ms = now.saturating_sub(request_start).as_millis() as u64;
+            tracing::Span::current().record("queue_time_ms", queue_time_ms);
+        }
+    }
+    next.run(req).await
+}
+
+/// Parses `t=<epoch>` or `<epoch>`, in seconds (possibly with a fraction), milliseconds or
+/// microseconds, told apart by their magnitude.
+pub fn parse_request_start(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let value = value.strip_prefix("t=").unwrap_or(value);
+    // the integers are kept exact, a float loses the last millisecond
+    if let Ok(epoch) = value.parse::<u64>() {
+        return Some(match epoch {
+            e if e > 100_000_000_000_000 => Duration::from_micros(e),
+            e if e > 100_000_000_000 => Duration::from_millis(e),
+            e => Duration::from_secs(e),
+        });
+    }
+    let secs = match value.parse::<f64>().ok()? {
+        e if e > 1e14 => e / 1e6,
+        e if e > 1e11 => e / 1e3,
+        e => e,
+    };
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+/// Rejects URIs longer than `MAX_URI_LEN` with a `414`.
+///
+/// It runs inside [`trace_requests`] so the rejection is traced, which only logs the truncated
+/// path of these URIs.
+pub async fn limit_uri_len<B>(
+    State(state): State<SharedState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, AppError> {
+    let max_uri_len = state.config().max_uri_len;
+    let uri = req.uri().to_string();
+    if max_uri_len > 0 && uri.len() > max_uri_len {
+        tracing::warn!(
+            http.method = %req.method(),
+            http.url = truncate(&uri, LOGGED_URI_LEN),
+            uri_len = uri.len(),
+            "URI too long"
+        );
+        return Err(AppError::UriTooLong);
+    }
+    Ok(next.run(req).await)
+}
+
+/// Fails the requests taking longer than `READ_TIMEOUT_MS` (`GET`, `HEAD` and `OPTIONS`) or
+/// `WRITE_TIMEOUT_MS` (the other methods) with a `408`.
+pub async fn limit_duration<B>(
+    State(state): State<SharedState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, AppError> {
+    let config = state.config();
+    let is_read = matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    let timeout_ms = if is_read {
+        config.read_timeout_ms
+    } else {
+        config.write_timeout_ms
+    };
+    let Some(timeout_ms) = timeout_ms else {
+        return Ok(next.run(req).await);
+    };
+    let method = req.method().clone();
+    tokio::time::timeout(Duration::from_millis(timeout_ms), next.run(req))
+        .await
+        .map_err(|_| {
+            tracing::warn!(http.method = %method, timeout_ms, "request timed out");
+            AppError::Timeout
+        })
+}
+
+/// Header every request must have, set with `REQUIRE_HEADER` (`name=value` or just `name`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RequiredHeader {
+    /// Lowercased.
+    pub name: String,
+    /// Any value is accepted when it's `None`.
+    pub value: Option<String>,
+}
+
+impl RequiredHeader {
+    pub fn parse(require_header: &str) -> anyhow::Result<Self> {
+        let (name, value) = match require_header.split_once('=') {
+            Some((name, value)) => (name, Some(value.trim().to_string())),
+            None => (require_header, None),
+        };
+        let name = name.trim().to_lowercase();
+        if HeaderName::from_bytes(name.as_bytes()).is_err() {
+            anyhow::bail!("invalid REQUIRE_HEADER name: {name}");
+        }
+        Ok(Self { name, value })
+    }
+}
+
+/// Rejects the requests without the `REQUIRE_HEADER` header (`400`) or with another value
+/// (`403`).
+///
+/// The health routes are exempt, the platform probes don't go through the gateway.
+pub async fn require_header<B>(
+    State(state): State<SharedState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Result<Response, AppError> {
+    let Some(required) = state.config().require_header else {
+        return Ok(next.run(req).await);
+    };
+    if HEALTH_ROUTES.contains(&req.uri().path()) {
+        return Ok(next.run(req).await);
+    }
+    match (req.headers().get(&required.name), &required.value) {
+        (None, _) => Err(AppError::BadRequest(format!(
+            "missing {} header",
+            required.name
+        ))),
+        (Some(value), Some(expected))
+            if !signature::secrets_match(value.as_bytes(), expected.as_bytes()) =>
+        {
+            Err(AppError::Forbidden)
+        }
+        _ => Ok(next.run(req).await),
+    }
+}
+
+/// The first `max` bytes of `value` (or less, to stay on a char boundary).
+fn truncate(value: &str, max: usize) -> &str {
+    let mut end = max.min(value.len());
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+/// Owner of the matched route, falling back to [`DEFAULT_OWNER`].
+pub fn owner(route: Option<&str>) -> &'static str {
+    route
+        .and_then(|route| ROUTE_OWNERS.iter().find(|(path, _)| *path == route))
+        .map(|(_, owner)| *owner)
+        .unwrap_or(DEFAULT_OWNER)
+}
+
+/// Datadog log status for the HTTP status, so 5xx show up as errors whatever the log level.
+pub fn dd_status(status: StatusCode) -> &'static str {
+    if status.is_server_error() {
+        "error"
+    } else if status.is_client_error() {
+        "warn"
+    } else {
+        "info"
+    }
+}
+
+/// Whose fault the error was: `client` for 4xx, `server` for 5xx.
+pub fn error_class(status: StatusCode) -> &'static str {
+    if status.is_client_error() {
+        "client"
+    } else if status.is_server_error() {
+        "server"
+    } else {
+        "none"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, send, Logs, TestState};
+    use axum::{body::Body, extract::Path, routing::get, Router};
+
+    /// `/status/:code` answers with `code`, behind the request tracing.
+    fn app(test: &TestState) -> Router {
+        Router::new()
+            .route(
+                "/status/:code",
+                get(|Path(code): Path<u16>| async move { StatusCode::from_u16(code).unwrap() }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                test.state.clone(),
+                record_queue_time,
+            ))
+            .layer(axum::middleware::from_fn_with_state(
+                test.state.clone(),
+                trace_requests,
+            ))
+    }
+
+    fn request(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn completion_log_has_the_error_class() {
+        let test = test_support::state(&[]);
+        for (code, class) in [(200, "none"), (400, "client"), (500, "server")] {
+            let (logs, _guard) = Logs::capture();
+            send(app(&test), request(&format!("/status/{code}"))).await;
+
+            let completed = logs.find("request completed").expect("no completion log");
+            assert_eq!(completed["error_class"], class, "{code}");
+            assert_eq!(completed["http.status_code"], code);
+        }
+    }
+
+    #[tokio::test]
+    async fn request_span_has_the_route_owner() {
+        let test = test_support::state(&[]);
+
+        for (uri, owner) in [
+            ("/health", "sre"),
+            ("/", "platform"),
+            ("/missing", DEFAULT_OWNER),
+        ] {
+            let (logs, _guard) = Logs::capture();
+            send(test.router(), request(uri)).await;
+
+            let completed = logs.find("request completed").expect("no completion log");
+            assert_eq!(completed["span"]["owner"], owner, "{uri}");
+        }
+    }
+
+    #[tokio::test]
+    async fn over_long_uris_are_rejected_and_never_logged_in_full() {
+        let test = test_support::state(&[("MAX_URI_LEN", "100")]);
+        let uri = format!("/{}", "a".repeat(500));
+        let (logs, _guard) = Logs::capture();
+
+        let response = send(test.router(), request(&uri)).await;
+
+        assert_eq!(response.status(), StatusCode::URI_TOO_LONG);
+        let events = logs.events();
+        assert!(events
+            .iter()
+            .any(|event| event["message"] == "URI too long"));
+        assert!(events
+            .iter()
+            .any(|event| event["message"] == "request completed"));
+        for event in events {
+            assert!(!event.to_string().contains(&uri), "{event}");
+        }
+    }
+
+    #[tokio::test]
+    async fn uris_within_the_limit_are_served() {
+        let test = test_support::state(&[("MAX_URI_LEN", "100")]);
+
+        let response = send(test.router(), request("/health?check=1")).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[test]
+    fn truncate_stays_on_char_boundaries() {
+        assert_eq!(truncate("abcdef", 3), "abc");
+        assert_eq!(truncate("ab", 3), "ab");
+        assert_eq!(truncate("aé", 2), "a");
+    }
+
+    #[tokio::test]
+    async fn response_traceparent_has_the_request_trace_id() {
+        let test = test_support::state(&[]);
+        let (logs, _guard) = Logs::capture();
+
+        let response = send(app(&test), request("/status/200")).await;
+
+        let header = response.headers()[TRACEPARENT].to_str().unwrap();
+        let trace_parent = TraceParent::parse(header).expect("invalid traceparent");
+        let completed = logs.find("request completed").expect("no completion log");
+        assert_eq!(completed["span"]["trace_id"], trace_parent.trace_id_hex());
+    }
+
+    #[tokio::test]
+    async fn incoming_traceparent_is_continued() {
+        let test = test_support::state(&[]);
+        let incoming = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let req = Request::get("/status/200")
+            .header(TRACEPARENT, incoming)
+            .body(Body::empty())
+            .unwrap();
+
+        let response = send(app(&test), req).await;
+
+        let header = response.headers()[TRACEPARENT].to_str().unwrap();
+        let trace_parent = TraceParent::parse(header).expect("invalid traceparent");
+        assert_eq!(
+            trace_parent.trace_id_hex(),
+            "4bf92f3577b34da6a3ce929d0e0e4736"
+        );
+        assert_ne!(trace_parent.parent_id, 0x00f0_67aa_0ba9_02b7);
+        assert!(trace_parent.sampled);
+    }
+
+    #[test]
+    fn request_start_formats() {
+        // the integers are exact, only the fractions of seconds are parsed as floats
+        let micros = |value| parse_request_start(value).map(|start| start.as_micros());
+        assert_eq!(micros("t=1700000000123"), Some(1_700_000_000_123_000));
+        assert_eq!(micros("1700000000123"), Some(1_700_000_000_123_000));
+        assert_eq!(micros("t=1700000000123456"), Some(1_700_000_000_123_456));
+        assert_eq!(micros("t=1700000000"), Some(1_700_000_000_000_000));
+        assert_eq!(micros("t=1700000000.5"), Some(1_700_000_000_500_000));
+        assert_eq!(micros("t=soon"), None);
+        assert_eq!(micros("t=-1"), None);
+    }
+
+    #[tokio::test]
+    async fn queue_time_is_recorded_behind_a_trusted_proxy() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let request_start = format!("t={}", (now - Duration::from_millis(1500)).as_millis());
+
+        for (trust_proxy, queued) in [("true", true), ("false", false)] {
+            let test = test_support::state(&[("TRUST_PROXY", trust_proxy)]);
+            let (logs, _guard) = Logs::capture();
+            let req = Request::get("/status/200")
+                .header("x-request-start", &request_start)
+                .body(Body::empty())
+                .unwrap();
+            send(app(&test), req).await;
+
+            let completed = logs.find("request completed").expect("no completion log");
+            let queue_time_ms = completed["span"]["queue_time_ms"].as_u64();
+            if queued {
+                assert!(
+                    queue_time_ms.is_some_and(|ms| (1500..60_000).contains(&ms)),
+                    "{queue_time_ms:?}"
+                );
+            } else {
+                assert_eq!(queue_time_ms, None);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn request_logs_have_the_pod_name_as_host() {
+        // the only tests setting it, both to the same value
+        std::env::set_var("POD_NAME", "web-7d9f");
+        let test = test_support::state(&[("TAG_POD_NAME", "true")]);
+        let (logs, _guard) = Logs::capture();
+
+        send(app(&test), request("/status/200")).await;
+
+        let completed = logs.find("request completed").expect("no completion log");
+        assert_eq!(completed["span"]["host"], "web-7d9f");
+    }
+
+    #[tokio::test]
+    async fn health_check_logs_are_sampled() {
+        let test = test_support::state(&[("HEALTH_LOG_SAMPLE", "0.1")]);
+        let (logs, _guard) = Logs::capture();
+
+        for _ in 0..20 {
+            send(test.router(), request("/health")).await;
+            send(test.router(), request("/")).await;
+        }
+
+        let completed = |route: &str| {
+            logs.events()
+                .iter()
+                .filter(|event| event["message"] == "request completed")
+                .filter(|event| event["span"]["http.route"] == route)
+                .count()
+        };
+        assert_eq!(completed("/health"), 2);
+        assert_eq!(completed("/"), 20);
+    }
+
+    #[tokio::test]
+    async fn completion_log_has_the_datadog_status() {
+        let test = test_support::state(&[]);
+
+        for (code, status) in [(500, "error"), (404, "warn"), (200, "info"), (302, "info")] {
+            let (logs, _guard) = Logs::capture();
+            send(app(&test), request(&format!("/status/{code}"))).await;
+
+            let completed = logs.find("request completed").expect("no completion log");
+            assert_eq!(completed["status"], status, "{code}");
+            // it's not the level of the log
+            assert_eq!(completed["level"], "INFO", "{code}");
+        }
+    }
+
+    #[tokio::test]
+    async fn handler_logs_carry_the_tenant_id() {
+        let test = test_support::state(&[("TENANT_HEADER", "X-Tenant-Id")]);
+        let (logs, _guard) = Logs::capture();
+
+        let req = Request::get("/")
+            .header("x-tenant-id", "acme")
+            .body(Body::empty())
+            .unwrap();
+        send(test.router(), req).await;
+
+        let hello = logs.find("Saying hello").expect("no handler log");
+        let request_span = hello["spans"]
+            .as_array()
+            .and_then(|spans| spans.iter().find(|span| span["name"] == "request"))
+            .expect("no request span");
+        assert_eq!(request_span["tenant_id"], "acme");
+    }
+
+    #[tokio::test]
+    async fn every_datadog_log_of_a_request_has_the_request_fields() {
+        // the only tests setting it, both to the same value
+        std::env::set_var("POD_NAME", "web-7d9f");
+        let (test, shipped) = TestState::with_datadog(test_support::secrets(&[
+            ("TENANT_HEADER", "X-Tenant-Id"),
+            ("TAG_POD_NAME", "true"),
+            ("TRUST_PROXY", "true"),
+            ("LOG_QUERY", "true"),
+        ]));
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let req = Request::get("/?page=2")
+            .header("x-tenant-id", "acme")
+            .header("x-feature-flags", "shout")
+            .header("x-request-start", format!("t={}", now.as_millis()))
+            .body(Body::empty())
+            .unwrap();
+
+        send(test.router(), req).await;
+        test.state.datadog.flush().await;
+
+        let logs = shipped.logs();
+        let request_span = |message: &str| {
+            let log = logs
+                .iter()
+                .find(|log| log["message"] == message)
+                .unwrap_or_else(|| panic!("{message} wasn't shipped"));
+            assert!(log["ddtags"].as_str().unwrap().ends_with(",tenant:acme"));
+            log["spans"][0].clone()
+        };
+        for message in ["Saying hello", "request completed"] {
+            let span = request_span(message);
+            assert_eq!(span["name"], "request", "{message}");
+            for field in ["trace_id", "request_id", "queue_time_ms"] {
+                assert!(!span[field].is_null(), "{message}: no {field}");
+            }
+            assert_eq!(span["tenant_id"], "acme", "{message}");
+            assert_eq!(span["owner"], "platform", "{message}");
+            assert_eq!(span["host"], "web-7d9f", "{message}");
+            assert_eq!(span["http.query"], "page=2", "{message}");
+            assert_eq!(span["feature_flags"], "shout", "{message}");
+        }
+        let completed = request_span("request completed");
+        assert_eq!(completed["http.status_code"], 200);
+        assert_eq!(completed["error_class"], "none");
+    }
+
+    #[tokio::test]
+    async fn tenant_id_is_truncated_and_optional() {
+        let test = test_support::state(&[("TENANT_HEADER", "X-Tenant-Id")]);
+        let (logs, _guard) = Logs::capture();
+        let req = Request::get("/status/200")
+            .header("x-tenant-id", "t".repeat(TENANT_ID_LEN * 2))
+            .body(Body::empty())
+            .unwrap();
+        send(app(&test), req).await;
+        send(app(&test), request("/status/200")).await;
+
+        let completed = logs
+            .events()
+            .into_iter()
+            .filter(|event| event["message"] == "request completed")
+            .collect::<Vec<_>>();
+        assert_eq!(completed[0]["span"]["tenant_id"], "t".repeat(TENANT_ID_LEN));
+        assert!(completed[1]["span"].get("tenant_id").is_none());
+    }
+
+    #[tokio::test]
+    async fn completion_log_has_the_response_content_type() {
+        let test = test_support::state(&[]);
+
+        for (uri, content_type) in [
+            ("/health", Some("application/json")),
+            ("/", Some("text/plain; charset=utf-8")),
+            ("/missing", None),
+        ] {
+            let (logs, _guard) = Logs::capture();
+            send(test.router(), request(uri)).await;
+
+            let completed = logs.find("request completed").expect("no completion log");
+            assert_eq!(
+                completed["response.content_type"].as_str(),
+                content_type,
+                "{uri}"
+            );
+        }
+    }
+
+    fn request_with(uri: &str, header: Option<&str>) -> Request<Body> {
+        let mut req = Request::get(uri);
+        if let Some(value) = header {
+            req = req.header("x-api-gateway", value);
+        }
+        req.body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn required_header_is_enforced() {
+        let test = test_support::state(&[("REQUIRE_HEADER", "X-Api-Gateway=gateway-secret")]);
+
+        for (header, status) in [
+            (Some("gateway-secret"), StatusCode::OK),
+            (Some("gateway-secre"), StatusCode::FORBIDDEN),
+            (None, StatusCode::BAD_REQUEST),
+        ] {
+            let response = send(test.router(), request_with("/", header)).await;
+            assert_eq!(response.status(), status, "{header:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn required_header_without_value_only_has_to_be_present() {
+        let test = test_support::state(&[("REQUIRE_HEADER", "X-Api-Gateway")]);
+
+        let response = send(test.router(), request_with("/", Some("anything"))).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(test.router(), request_with("/", None)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn health_routes_dont_require_the_header() {
+        let test = test_support::state(&[("REQUIRE_HEADER", "X-Api-Gateway=gateway-secret")]);
+
+        for uri in HEALTH_ROUTES {
+            let response = send(test.router(), request_with(uri, None)).await;
+            assert_eq!(response.status(), StatusCode::OK, "{uri}");
+        }
+    }
+
+    #[test]
+    fn required_header_value_is_never_logged() {
+        let (logs, _guard) = Logs::capture();
+
+        let _test = test_support::state(&[("REQUIRE_HEADER", "X-Api-Gateway=gateway-secret")]);
+
+        let resolved = logs
+            .events()
+            .into_iter()
+            .find(|event| event["key"] == "REQUIRE_HEADER")
+            .expect("REQUIRE_HEADER resolution not logged");
+        assert!(resolved.get("value").is_none());
+        assert!(!format!("{:?}", logs.events()).contains("gateway-secret"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timeouts_depend_on_the_method() {
+        let test = test_support::state(&[("READ_TIMEOUT_MS", "100"), ("WRITE_TIMEOUT_MS", "1000")]);
+        // answers after `ms`, whatever the method
+        let router = Router::new().route(
+            "/sleep/:ms",
+            axum::routing::any(|Path(ms): Path<u64>| async move {
+                tokio::time::sleep(Duration::from_millis(ms)).await;
+                StatusCode::OK
+            }),
+        );
+        let app = crate::with_layers(router, test.state.clone());
+
+        for (method, ms, status) in [
+            (Method::GET, 50, StatusCode::OK),
+            (Method::GET, 500, StatusCode::REQUEST_TIMEOUT),
+            (Method::POST, 500, StatusCode::OK),
+            (Method::POST, 1500, StatusCode::REQUEST_TIMEOUT),
+        ] {
+            let req = Request::builder()
+                .method(method.clone())
+                .uri(format!("/sleep/{ms}"))
+                .body(Body::empty())
+                .unwrap();
+            let response = send(app.clone(), req).await;
+            assert_eq!(response.status(), status, "{method} {ms}");
+        }
+    }
+}
@@ -0,0 +1,200 @@
+use crate::state::SharedState;
+use axum::{extract::State, Json};
+use serde_json::{json, Value};
+
+/// Hand-maintained, there are few routes. Keep it in sync with `build_router`.
+fn paths() -> [(&'static str, Value); 7] {
+    let text = |description: &str| {
+        json!({
+            "description": description,
+            "content": { "text/plain": { "schema": { "type": "string" } } },
+        })
+    };
+    [
+        (
+            "/",
+            json!({
+                "get": {
+                    "summary": "Says hello",
+                    "responses": { "200": text("`Hello, world!`") },
+                },
+            }),
+        ),
+        (
+            "/health",
+            json!({
+                "get": {
+                    "summary": "Liveness check",
+                    "responses": {
+                        "200": {
+                            "description": "`{\"status\":\"ok\"}`, or `OK` with `HEALTH_BODY=ok`",
+                            "content": {
+                                "application/json": {
+                                    "schema": {
+                                        "type": "object",
+                                        "properties": { "status": { "type": "string" } },
+                                    },
+                                },
+                                "text/plain": { "schema": { "type": "string" } },
+                            },
+                        },
+                    },
+                },
+            }),
+        ),
+        (
+            "/ready",
+            json!({
+                "get": {
+                    "summary": "Readiness check",
+                    "responses": {
+                        "200": { "$ref": "#/components/responses/Readiness" },
+                        "503": { "$ref": "#/components/responses/Readiness" },
+                    },
+                },
+            }),
+        ),
+        (
+            "/robots.txt",
+            json!({ "get": { "responses": { "200": text("robots.txt") } } }),
+        ),
+        (
+            "/.well-known/security.txt",
+            json!({ "get": { "responses": { "200": text("security.txt") } } }),
+        ),
+        (
+            "/openapi.json",
+            json!({
+                "get": {
+                    "summary": "This document",
+                    "responses": {
+                        "200": {
+                            "description": "OpenAPI 3.0 document",
+                            "content": { "application/json": {} },
+                        },
+                    },
+                },
+            }),
+        ),
+        (
+            "/metrics",
+            json!({
+                "get": {
+                    "summary": "Datadog log shipping metrics",
+                    "responses": {
+                        "200": {
+                            "description": "`dd.queue.depth`, `dd.logs.shipped` and `dd.logs.failed`",
+                            "content": { "application/json": {} },
+                        },
+                    },
+                },
+            }),
+        ),
+    ]
+}
+
+/// `/openapi.json`, describing the public routes that aren't in `DISABLED_ROUTES`.
+///
+/// `/admin` is left out, it's not meant for clients.
+pub async fn openapi(State(state): State<SharedState>) -> Json<Value> {
+    let disabled_routes = state.config().disabled_routes;
+    let paths = paths()
+        .into_iter()
+        .filter(|(path, _)| !disabled_routes.iter().any(|route| route == path))
+        .map(|(path, item)| (path.to_string(), item))
+        .collect::<serde_json::Map<_, _>>();
+    Json(json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": paths,
+        "components": {
+            "responses": {
+                "Readiness": {
+                    "description": "`ready`, or `draining`/`not_ready` with a `503`",
+                    "content": {
+                        "application/json": {
+                            "schema": {
+                                "type": "object",
+                                "properties": {
+                                    "status": {
+                                        "type": "string",
+                                        "enum": ["ready", "draining", "not_ready"],
+                                    },
+                                    "checks": {
+                                        "type": "object",
+                                        "additionalProperties": {
+                                            "type": "string",
+                                            "enum": ["ok", "failed"],
+                                        },
+                                    },
+                                },
+                            },
+                        },
+                    },
+                },
+            },
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::{self, body_string, send};
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+    };
+    use serde_json::Value;
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    async fn document(pairs: &[(&str, &str)]) -> Value {
+        let test = test_support::state(pairs);
+        let response = send(test.router(), get("/openapi.json")).await;
+        serde_json::from_str(&body_string(response).await).unwrap()
+    }
+
+    #[tokio::test]
+    async fn document_lists_the_served_routes() {
+        let test = test_support::state(&[]);
+        let document = document(&[]).await;
+
+        assert_eq!(document["openapi"], "3.0.3");
+        let paths = document["paths"].as_object().unwrap();
+        assert_eq!(
+            paths.keys().map(String::as_str).collect::<Vec<_>>(),
+            [
+                "/",
+                "/.well-known/security.txt",
+                "/health",
+                "/metrics",
+                "/openapi.json",
+                "/ready",
+                "/robots.txt",
+            ]
+        );
+        for (path, item) in paths {
+            let methods = item.as_object().unwrap().keys().collect::<Vec<_>>();
+            assert_eq!(methods, ["get"], "{path}");
+            let response = send(test.router(), get(path)).await;
+            assert_ne!(response.status(), StatusCode::NOT_FOUND, "{path}");
+            assert_ne!(response.status(), StatusCode::METHOD_NOT_ALLOWED, "{path}");
+        }
+        let readiness = &document["paths"]["/ready"]["get"]["responses"]["200"]["$ref"];
+        assert_eq!(readiness, "#/components/responses/Readiness");
+        assert!(document["components"]["responses"]["Readiness"].is_object());
+    }
+
+    #[tokio::test]
+    async fn disabled_routes_are_left_out() {
+        let document = document(&[("DISABLED_ROUTES", "/robots.txt")]).await;
+
+        assert!(document["paths"].get("/robots.txt").is_none());
+        assert!(document["paths"].get("/health").is_some());
+    }
+}
@@ -0,0 +1,83 @@
+use crate::state::SharedState;
+use axum::{extract::State, http::Request, middleware::Next, response::Response};
+
+/// Query parameters masked by default.
+pub const DEFAULT_REDACTED_PARAMS: &[&str] = &[
+    "token",
+    "access_token",
+    "api_key",
+    "apikey",
+    "password",
+    "secret",
+];
+
+/// Records the query string in the `http.query` field of the request span, with the values
+/// of the `REDACT_QUERY_PARAMS` masked.
+pub async fn record_query<B>(
+    State(state): State<SharedState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if let Some(query) = req.uri().query() {
+        let redacted = redact_query(query, &state.config().redact_query_params);
+        tracing::Span::current().record("http.query", redacted);
+    }
+    next.run(req).await
+}
+
+/// Replaces the value of the `redacted` parameters (case insensitive) with `***`. The rest is
+/// kept as is, still percent-encoded.
+pub fn redact_query(query: &str, redacted: &[String]) -> String {
+    query
+        .split('&')
+        .map(|pair| match pair.split_once('=') {
+            Some((key, _)) if redacted.contains(&key.to_lowercase()) => format!("{key}=***"),
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, send, Logs};
+    use axum::body::Body;
+
+    fn defaults() -> Vec<String> {
+        DEFAULT_REDACTED_PARAMS
+            .iter()
+            .map(|param| param.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn sensitive_params_are_masked() {
+        assert_eq!(
+            redact_query("page=2&token=abc&API_KEY=def", &defaults()),
+            "page=2&token=***&API_KEY=***"
+        );
+        assert_eq!(
+            redact_query("q=a%20b&flag&password=", &defaults()),
+            "q=a%20b&flag&password=***"
+        );
+        assert_eq!(
+            redact_query("page=2&token=abc", &["page".to_string()]),
+            "page=***&token=abc"
+        );
+    }
+
+    #[tokio::test]
+    async fn logged_query_is_redacted() {
+        let test = test_support::state(&[("LOG_QUERY", "true")]);
+        let (logs, _guard) = Logs::capture();
+
+        let req = Request::get("/?page=2&token=abc")
+            .body(Body::empty())
+            .unwrap();
+        send(test.router(), req).await;
+
+        let completed = logs.find("request completed").expect("no completion log");
+        assert_eq!(completed["span"]["http.query"], "page=2&token=***");
+    }
+}
@@ -0,0 +1,142 @@
+use rand::Rng;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Crockford's base32, used by ULIDs.
+const ULID_ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+/// URL-safe alphabet of nanoid.
+const NANO_ALPHABET: &[u8; 64] =
+    b"useandom-26T198340PX75pxJACKVERYMINDBUSHWOLF_GQZbfghjklqvwyzrict";
+const NANO_LEN: usize = 21;
+
+/// How the request ids are generated, selected with `REQUEST_ID_FORMAT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestIdFormat {
+    /// Random UUID (v4), e.g. `9b2a4c4e-6f0f-4d3c-8a53-0e1d3c6b7f21`.
+    Uuid,
+    /// Sortable by creation time, e.g. `01HF3Z6Y8M6R5W2N9K4T7QX3BD`.
+    Ulid,
+    /// Short and URL-safe, 21 characters.
+    Nano,
+}
+
+impl RequestIdFormat {
+    pub fn parse(format: &str) -> anyhow::Result<Self> {
+        match format.trim().to_lowercase().as_str() {
+            "uuid" => Ok(Self::Uuid),
+            "ulid" => Ok(Self::Ulid),
+            "nano" => Ok(Self::Nano),
+            _ => anyhow::bail!("invalid REQUEST_ID_FORMAT: {format}"),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Uuid => "uuid",
+            Self::Ulid => "ulid",
+            Self::Nano => "nano",
+        }
+    }
+
+    pub fn generate(&self) -> String {
+        match self {
+            Self::Uuid => uuid_v4(),
+            Self::Ulid => ulid(),
+            Self::Nano => nano_id(),
+        }
+    }
+}
+
+fn uuid_v4() -> String {
+    let mut bytes = rand::random::<[u8; 16]>();
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex = bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<String>();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+/// 48 bits of milliseconds since the epoch followed by 80 random bits, in base32.
+fn ulid() -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    let random = rand::random::<u128>() & ((1 << 80) - 1);
+    let value = ((millis & ((1 << 48) - 1)) << 80) | random;
+    (0..26)
+        .rev()
+        .map(|i| ULID_ALPHABET[((value >> (i * 5)) & 0x1f) as usize] as char)
+        .collect()
+}
+
+fn nano_id() -> String {
+    let mut rng = rand::thread_rng();
+    (0..NANO_LEN)
+        .map(|_| NANO_ALPHABET[rng.gen_range(0..NANO_ALPHABET.len())] as char)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn uuids_are_v4() {
+        let id = RequestIdFormat::Uuid.generate();
+
+        let groups = id.split('-').map(str::len).collect::<Vec<_>>();
+        assert_eq!(groups, [8, 4, 4, 4, 12], "{id}");
+        assert!(id
+            .chars()
+            .all(|c| c == '-' || c.is_ascii_digit() || ('a'..='f').contains(&c)));
+        assert_eq!(&id[14..15], "4", "{id}");
+        assert!(matches!(&id[19..20], "8" | "9" | "a" | "b"), "{id}");
+    }
+
+    #[test]
+    fn ulids_are_sortable() {
+        let first = RequestIdFormat::Ulid.generate();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+        let second = RequestIdFormat::Ulid.generate();
+
+        for id in [&first, &second] {
+            assert_eq!(id.len(), 26);
+            assert!(id.bytes().all(|c| ULID_ALPHABET.contains(&c)), "{id}");
+        }
+        assert!(first < second, "{first} {second}");
+    }
+
+    #[test]
+    fn nano_ids_are_url_safe() {
+        let id = RequestIdFormat::Nano.generate();
+
+        assert_eq!(id.len(), NANO_LEN);
+        assert!(id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_ne!(id, RequestIdFormat::Nano.generate());
+    }
+
+    #[test]
+    fn formats_round_trip() {
+        for format in [
+            RequestIdFormat::Uuid,
+            RequestIdFormat::Ulid,
+            RequestIdFormat::Nano,
+        ] {
+            assert_eq!(RequestIdFormat::parse(format.as_str()).unwrap(), format);
+        }
+        assert!(RequestIdFormat::parse("snowflake").is_err());
+    }
+}
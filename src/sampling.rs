@@ -0,0 +1,110 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::{subscriber::Interest, Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+/// Only lets a fraction of the spans with the given name be created.
+///
+/// It works as a global filter, so unsampled spans are disabled for every layer and don't
+/// cost anything besides this check. Events inside them are still recorded.
+pub struct SpanSampler {
+    name: &'static str,
+    rate: f64,
+    count: AtomicU64,
+}
+
+impl SpanSampler {
+    /// `rate` must be between `0.0` (no spans) and `1.0` (every span).
+    pub fn new(name: &'static str, rate: f64) -> Self {
+        Self {
+            name,
+            rate,
+            count: AtomicU64::new(0),
+        }
+    }
+
+    fn applies_to(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.is_span() && metadata.name() == self.name
+    }
+
+    fn sample(&self) -> bool {
+        sample(&self.count, self.rate)
+    }
+}
+
+/// Deterministic sampling: lets through exactly `rate` of the calls sharing `count`, evenly
+/// spread.
+pub fn sample(count: &AtomicU64, rate: f64) -> bool {
+    let n = count.fetch_add(1, Ordering::Relaxed) as f64;
+    ((n + 1.0) * rate).floor() > (n * rate).floor()
+}
+
+impl<S: Subscriber> Layer<S> for SpanSampler {
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if self.applies_to(metadata) {
+            // we need `enabled` to be called for every span
+            Interest::sometimes()
+        } else {
+            Interest::always()
+        }
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, _ctx: Context<'_, S>) -> bool {
+        !self.applies_to(metadata) || self.sample()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Counter;
+    use tracing_subscriber::{prelude::*, Registry};
+
+    /// Creates 10 `hello_world` spans with an event each, returning the counts.
+    fn hello_world_spans(rate: f64) -> (u64, u64) {
+        let counter = Counter::default();
+        let subscriber = Registry::default()
+            .with(SpanSampler::new("hello_world", rate))
+            .with(counter.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            for _ in 0..10 {
+                tracing::info_span!("hello_world").in_scope(|| tracing::info!("Saying hello"));
+            }
+        });
+        (counter.spans(), counter.events())
+    }
+
+    #[test]
+    fn sample_lets_through_the_rate() {
+        let sampler = SpanSampler::new("hello_world", 0.25);
+        let sampled = (0..100).filter(|_| sampler.sample()).count();
+        assert_eq!(sampled, 25);
+
+        let (all, none) = (
+            SpanSampler::new("hello_world", 1.0),
+            SpanSampler::new("hello_world", 0.0),
+        );
+        assert!((0..10).all(|_| all.sample()));
+        assert!((0..10).all(|_| !none.sample()));
+    }
+
+    #[test]
+    fn unsampled_spans_are_not_created() {
+        assert_eq!(hello_world_spans(0.0), (0, 10));
+        assert_eq!(hello_world_spans(0.5), (5, 10));
+        assert_eq!(hello_world_spans(1.0), (10, 10));
+    }
+
+    #[test]
+    fn other_spans_are_always_created() {
+        let counter = Counter::default();
+        let subscriber = Registry::default()
+            .with(SpanSampler::new("hello_world", 0.0))
+            .with(counter.clone());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::info_span!("request").entered();
+        });
+
+        assert_eq!(counter.spans(), 1);
+    }
+}
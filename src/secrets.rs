@@ -0,0 +1,318 @@
+use shuttle_secrets::SecretStore;
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+/// Secrets whose values are never logged.
+const SENSITIVE_KEYS: &[&str] = &[
+    "DD_API_KEY",
+    "ADMIN_TOKEN",
+    "RESPONSE_SIGNING_KEY",
+    "ADMIN_HMAC_KEY",
+    // the value is a shared secret
+    "REQUIRE_HEADER",
+];
+
+/// Somewhere secrets can be read from.
+pub trait SecretProvider: Send + Sync {
+    fn get(&self, key: &str) -> Option<String>;
+
+    /// Name of the provider in the logs.
+    fn source(&self) -> &'static str;
+
+    /// Forgets anything cached, so the next reads see the current values.
+    fn refresh(&self) {}
+}
+
+impl SecretProvider for SecretStore {
+    fn get(&self, key: &str) -> Option<String> {
+        SecretStore::get(self, key)
+    }
+
+    fn source(&self) -> &'static str {
+        "shuttle"
+    }
+}
+
+/// Reads each secret from the environment variable with the same name.
+pub struct EnvSecrets;
+
+impl SecretProvider for EnvSecrets {
+    fn get(&self, key: &str) -> Option<String> {
+        std::env::var(key).ok()
+    }
+
+    fn source(&self) -> &'static str {
+        "env"
+    }
+}
+
+/// Reads each secret from `<dir>/<KEY>`, the way Docker and Kubernetes mount them.
+pub struct FileSecrets {
+    dir: PathBuf,
+    cache: Mutex<HashMap<String, Option<String>>>,
+}
+
+impl FileSecrets {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            cache: Mutex::default(),
+        }
+    }
+
+    fn read(&self, key: &str) -> Option<String> {
+        // the files usually end with a newline
+        std::fs::read_to_string(self.dir.join(key))
+            .ok()
+            .map(|value| value.trim().to_string())
+    }
+}
+
+impl SecretProvider for FileSecrets {
+    fn get(&self, key: &str) -> Option<String> {
+        let mut cache = self.cache.lock().unwrap_or_else(PoisonError::into_inner);
+        cache
+            .entry(key.to_string())
+            .or_insert_with(|| self.read(key))
+            .clone()
+    }
+
+    fn source(&self) -> &'static str {
+        "file"
+    }
+
+    fn refresh(&self) {
+        self.cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clear();
+    }
+}
+
+/// Every configured provider, the first one having the secret wins.
+///
+/// Each lookup is logged at debug level (the value only for non-sensitive keys), to have a
+/// trail of where the settings came from.
+pub struct Secrets {
+    providers: Vec<Box<dyn SecretProvider>>,
+    /// Lookups made before the tracing subscriber is set, see [`Secrets::log_pending`].
+    pending: Mutex<Vec<Resolution>>,
+}
+
+struct Resolution {
+    key: String,
+    source: &'static str,
+    value: Option<String>,
+}
+
+impl Resolution {
+    fn log(&self) {
+        tracing::debug!(
+            target: "config",
+            key = self.key,
+            source = self.source,
+            value = self.value,
+            "Secret resolved"
+        );
+    }
+}
+
+impl Secrets {
+    /// The Shuttle secrets, then the environment variables, then the files in `SECRETS_DIR`
+    /// (from either of them) if it's set.
+    pub fn new(secret_store: SecretStore) -> Self {
+        let secrets_dir = SecretProvider::get(&secret_store, "SECRETS_DIR")
+            .or_else(|| EnvSecrets.get("SECRETS_DIR"))
+            .filter(|dir| !dir.trim().is_empty());
+
+        let mut providers: Vec<Box<dyn SecretProvider>> =
+            vec![Box::new(secret_store), Box::new(EnvSecrets)];
+        if let Some(dir) = secrets_dir {
+            providers.push(Box::new(FileSecrets::new(dir)));
+        }
+        Self {
+            providers,
+            pending: Mutex::default(),
+        }
+    }
+
+    /// Logs the lookups made before the tracing subscriber was set.
+    pub fn log_pending(&self) {
+        let pending = std::mem::take(&mut *self.lock_pending());
+        for resolution in pending {
+            resolution.log();
+        }
+    }
+
+    fn lock_pending(&self) -> MutexGuard<'_, Vec<Resolution>> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn record(&self, key: &str, found: Option<(&'static str, &str)>) {
+        let resolution = Resolution {
+            key: key.to_string(),
+            source: found.map_or("unset", |(source, _)| source),
+            value: found
+                .filter(|_| !SENSITIVE_KEYS.contains(&key))
+                .map(|(_, value)| value.to_string()),
+        };
+        if tracing::dispatcher::has_been_set() {
+            resolution.log();
+        } else {
+            self.lock_pending().push(resolution);
+        }
+    }
+}
+
+impl SecretProvider for Secrets {
+    fn get(&self, key: &str) -> Option<String> {
+        let found = self
+            .providers
+            .iter()
+            .find_map(|provider| Some((provider.source(), provider.get(key)?)));
+        self.record(
+            key,
+            found
+                .as_ref()
+                .map(|(source, value)| (*source, value.as_str())),
+        );
+        found.map(|(_, value)| value)
+    }
+
+    fn source(&self) -> &'static str {
+        "secrets"
+    }
+
+    fn refresh(&self) {
+        for provider in &self.providers {
+            provider.refresh();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Logs;
+    use std::collections::BTreeMap;
+
+    /// A fresh directory for the secret files of the test.
+    fn secrets_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("secrets-{test}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn file_secrets_are_read_trimmed() {
+        let dir = secrets_dir("trimmed");
+        std::fs::write(dir.join("DD_API_KEY"), "  api-key\n").unwrap();
+        let secrets = FileSecrets::new(&dir);
+
+        assert_eq!(secrets.get("DD_API_KEY").as_deref(), Some("api-key"));
+        assert_eq!(secrets.get("DD_TAGS"), None);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn file_secrets_are_cached_until_refreshed() {
+        let dir = secrets_dir("cached");
+        std::fs::write(dir.join("LOG_LEVEL"), "info").unwrap();
+        let secrets = FileSecrets::new(&dir);
+        assert_eq!(secrets.get("LOG_LEVEL").as_deref(), Some("info"));
+
+        std::fs::write(dir.join("LOG_LEVEL"), "debug").unwrap();
+        assert_eq!(secrets.get("LOG_LEVEL").as_deref(), Some("info"));
+
+        secrets.refresh();
+        assert_eq!(secrets.get("LOG_LEVEL").as_deref(), Some("debug"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn shuttle_secrets_win_over_the_files() {
+        let dir = secrets_dir("precedence");
+        std::fs::write(dir.join("LOG_LEVEL"), "debug").unwrap();
+        std::fs::write(dir.join("DD_TAGS"), "team:web").unwrap();
+        let store = SecretStore::new(BTreeMap::from([
+            ("SECRETS_DIR".to_string(), dir.to_str().unwrap().to_string()),
+            ("LOG_LEVEL".to_string(), "warn".to_string()),
+        ]));
+        let secrets = Secrets::new(store);
+        let (logs, _guard) = Logs::capture();
+
+        assert_eq!(secrets.get("LOG_LEVEL").as_deref(), Some("warn"));
+        assert_eq!(secrets.get("DD_TAGS").as_deref(), Some("team:web"));
+        let sources = logs
+            .events()
+            .into_iter()
+            .map(|event| event["source"].clone())
+            .collect::<Vec<_>>();
+        assert_eq!(sources, ["shuttle", "file"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn env_vars_come_between_the_shuttle_secrets_and_the_files() {
+        // no setting has these names, they can't leak into the other tests
+        std::env::set_var("SECRETS_TEST_IN_SHUTTLE", "env");
+        std::env::set_var("SECRETS_TEST_IN_ENV", "env");
+        let dir = secrets_dir("env");
+        for key in [
+            "SECRETS_TEST_IN_SHUTTLE",
+            "SECRETS_TEST_IN_ENV",
+            "SECRETS_TEST_IN_FILE",
+        ] {
+            std::fs::write(dir.join(key), "file").unwrap();
+        }
+        let store = SecretStore::new(BTreeMap::from([
+            ("SECRETS_DIR".to_string(), dir.to_str().unwrap().to_string()),
+            ("SECRETS_TEST_IN_SHUTTLE".to_string(), "shuttle".to_string()),
+        ]));
+        let secrets = Secrets::new(store);
+
+        assert_eq!(
+            secrets.get("SECRETS_TEST_IN_SHUTTLE").as_deref(),
+            Some("shuttle")
+        );
+        assert_eq!(secrets.get("SECRETS_TEST_IN_ENV").as_deref(), Some("env"));
+        assert_eq!(secrets.get("SECRETS_TEST_IN_FILE").as_deref(), Some("file"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn resolutions_are_logged_without_the_sensitive_values() {
+        let store = SecretStore::new(BTreeMap::from([
+            ("DD_API_KEY".to_string(), "api-key".to_string()),
+            ("LOG_LEVEL".to_string(), "warn".to_string()),
+        ]));
+        let secrets = Secrets::new(store);
+        let (logs, _guard) = Logs::capture();
+
+        for key in ["DD_API_KEY", "LOG_LEVEL", "DD_TAGS"] {
+            secrets.get(key);
+        }
+
+        let events = logs.events();
+        assert_eq!(events.len(), 3);
+        for (event, (key, source, value)) in events.iter().zip([
+            ("DD_API_KEY", "shuttle", None),
+            ("LOG_LEVEL", "shuttle", Some("warn")),
+            ("DD_TAGS", "unset", None),
+        ]) {
+            assert_eq!(event["message"], "Secret resolved");
+            assert_eq!(event["level"], "DEBUG");
+            assert_eq!(event["target"], "config");
+            assert_eq!(event["key"], key);
+            assert_eq!(event["source"], source);
+            assert_eq!(event["value"].as_str(), value, "{key}");
+        }
+        assert!(!logs
+            .events()
+            .iter()
+            .any(|event| event.to_string().contains("api-key")));
+    }
+}
@@ -0,0 +1,679 @@
+use crate::{
+    build_info,
+    datadog::{self, DatadogHandle},
+    state::SharedState,
+};
+use axum::Router;
+use hyper::server::conn::AddrIncoming;
+use shuttle_runtime::{CustomError, Error};
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    time::{Duration, Instant},
+};
+
+/// How the startup is logged, selected with `STARTUP_LOG_STYLE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StartupLogStyle {
+    /// An event per step.
+    Verbose,
+    /// A single `startup` event with all of them (warnings are still logged on their own).
+    Summary,
+}
+
+impl StartupLogStyle {
+    pub fn parse(style: &str) -> anyhow::Result<Self> {
+        match style.trim().to_lowercase().as_str() {
+            "verbose" => Ok(Self::Verbose),
+            "summary" => Ok(Self::Summary),
+            _ => anyhow::bail!("invalid STARTUP_LOG_STYLE: {style}"),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Verbose => "verbose",
+            Self::Summary => "summary",
+        }
+    }
+}
+
+/// Same as `shuttle_axum::AxumService`, but we own the bind so we know when the server is
+/// ready to accept connections, and can shut it down on our own.
+pub struct AppService {
+    router: Router,
+    admin_router: Option<Router>,
+    state: SharedState,
+    started_at: Instant,
+}
+
+impl AppService {
+    /// `started_at` should be taken as early as possible in the entry point.
+    ///
+    /// `admin_router` is served on `127.0.0.1:<ADMIN_PORT>`, next to the main listener.
+    pub fn new(
+        router: Router,
+        admin_router: Option<Router>,
+        state: SharedState,
+        started_at: Instant,
+    ) -> Self {
+        Self {
+            router,
+            admin_router,
+            state,
+            started_at,
+        }
+    }
+}
+
+#[shuttle_runtime::async_trait]
+impl shuttle_runtime::Service for AppService {
+    async fn bind(mut self, addr: SocketAddr) -> Result<(), Error> {
+        let config = self.state.config();
+
+        // h2c replaces HTTP/1.1 instead of being negotiated, so only one of them is ever served
+        let mut server = try_bind(&addr)?.http2_only(config.http2_cleartext);
+        if let Some(max_header_bytes) = config.max_header_bytes {
+            server = server.http1_max_buf_size(max_header_bytes);
+        }
+        let verbose = config.startup_log_style == StartupLogStyle::Verbose;
+        let duration_ms = elapsed_ms(self.started_at);
+        if verbose {
+            tracing::info!(
+                duration_ms,
+                http2_cleartext = config.http2_cleartext,
+                "cold_start"
+            );
+        }
+        if let Some(startup_slo_ms) = config.startup_slo_ms.filter(|slo| duration_ms > *slo) {
+            tracing::warn!(duration_ms, startup_slo_ms, "slow startup");
+        }
+
+        let admin_addr = self
+            .admin_router
+            .as_ref()
+            .and(config.admin_port)
+            .map(|admin_port| SocketAddr::from(([127, 0, 0, 1], admin_port)));
+        // bound before serving anything, a port in use should fail the deployment
+        let admin_server = match (self.admin_router, admin_addr) {
+            (Some(admin_router), Some(admin_addr)) => {
+                let admin_server = try_bind(&admin_addr)?
+                    .serve(admin_router.into_make_service_with_connect_info::<SocketAddr>())
+                    .with_graceful_shutdown(shutdown_signal(config.max_runtime_s, stop_signal()));
+                if verbose {
+                    tracing::info!(%admin_addr, "admin listener started");
+                }
+                Some(tokio::spawn(admin_server))
+            }
+            _ => None,
+        };
+
+        if !verbose {
+            tracing::info!(
+                duration_ms,
+                http2_cleartext = config.http2_cleartext,
+                admin_enabled = config.admin_token.is_some(),
+                admin_addr = admin_addr.map(|admin_addr| admin_addr.to_string()),
+                profile = build_info::PROFILE,
+                build_time = build_info::BUILD_TIMESTAMP,
+                git_branch = build_info::GIT_BRANCH,
+                worker_threads = worker_threads(),
+                "startup"
+            );
+        }
+
+        let heartbeat = (config.heartbeat_interval_s > 0).then(|| {
+            tokio::spawn(heartbeat(
+                Duration::from_secs(config.heartbeat_interval_s),
+                self.started_at,
+            ))
+        });
+
+        let dd_queue = (config.dd_queue_report_interval_s > 0).then(|| {
+            tokio::spawn(report_dd_queue(
+                Duration::from_secs(config.dd_queue_report_interval_s),
+                self.state.datadog.clone(),
+            ))
+        });
+        #[cfg(unix)]
+        let flush_on_sigusr1 = config
+            .sigusr1_flush
+            .then(|| tokio::spawn(flush_on_sigusr1(self.state.datadog.clone())));
+        #[cfg(not(unix))]
+        let flush_on_sigusr1: Option<tokio::task::JoinHandle<()>> = None;
+
+        let served = server
+            .serve(
+                self.router
+                    .into_make_service_with_connect_info::<SocketAddr>(),
+            )
+            .with_graceful_shutdown(shutdown_signal(config.max_runtime_s, stop_signal()))
+            .await;
+        for task in [heartbeat, dd_queue, flush_on_sigusr1]
+            .into_iter()
+            .flatten()
+        {
+            task.abort();
+        }
+        if let Some(admin_server) = admin_server {
+            if served.is_ok() {
+                // same shutdown signal, so it's stopping too
+                match admin_server.await {
+                    Ok(Err(e)) => tracing::error!(error = %e, "admin listener failed"),
+                    Err(e) => tracing::error!(error = %e, "admin listener panicked"),
+                    Ok(Ok(())) => {}
+                }
+            } else {
+                admin_server.abort();
+            }
+        }
+        served.map_err(CustomError::new)?;
+
+        tracing::info!("Server stopped");
+        // nothing else is logged, so this is the last of them; Datadog doesn't get it
+        let flushed = self.state.datadog.flush().await;
+        tracing::info!(target: datadog::TARGET, flushed, "flushed {flushed} logs on shutdown");
+        Ok(())
+    }
+}
+
+/// Binds `addr`, logging why it failed if it did.
+fn try_bind(addr: &SocketAddr) -> Result<hyper::server::Builder<AddrIncoming>, Error> {
+    axum::Server::try_bind(addr).map_err(|e| {
+        let diagnosis = bind_diagnosis(&e);
+        tracing::error!(%addr, error = %e, diagnosis, "failed to bind");
+        CustomError::new(e).into()
+    })
+}
+
+/// What went wrong binding, from the IO error behind the hyper one.
+fn bind_diagnosis(error: &hyper::Error) -> &'static str {
+    let kind = std::error::Error::source(error)
+        .and_then(|source| source.downcast_ref::<io::Error>())
+        .map(io::Error::kind);
+    match kind {
+        Some(io::ErrorKind::AddrInUse) => "address_in_use",
+        Some(io::ErrorKind::PermissionDenied) => "permission_denied",
+        _ => "other",
+    }
+}
+
+/// Resolves when the server should stop: on `stop`, or once it ran for `max_runtime_s`
+/// (useful for test/ephemeral runs) even if `stop` never comes.
+async fn shutdown_signal(max_runtime_s: Option<u64>, stop: impl Future<Output = ()>) {
+    let max_runtime = async {
+        match max_runtime_s {
+            Some(secs) => {
+                tokio::time::sleep(Duration::from_secs(secs)).await;
+                tracing::info!(max_runtime_s = secs, "Max runtime reached, shutting down");
+            }
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        () = max_runtime => {}
+        () = stop => tracing::info!("Stop signal received, shutting down"),
+    }
+}
+
+/// Ctrl-C, or `SIGTERM` on Unix, so a normal stop drains the in-flight requests and
+/// flushes the logs.
+async fn stop_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::warn!(error = %e, "can't listen for Ctrl-C");
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!(error = %e, "can't listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        () = ctrl_c => {}
+        () = terminate => {}
+    }
+}
+
+/// Flushes the Datadog logs on every `SIGUSR1`, without stopping.
+#[cfg(unix)]
+async fn flush_on_sigusr1(datadog: DatadogHandle) {
+    use tokio::signal::unix::{signal, SignalKind};
+    let mut sigusr1 = match signal(SignalKind::user_defined1()) {
+        Ok(sigusr1) => sigusr1,
+        Err(e) => {
+            tracing::warn!(error = %e, "can't listen for SIGUSR1, SIGUSR1_FLUSH is ignored");
+            return;
+        }
+    };
+    while sigusr1.recv().await.is_some() {
+        let flushed = datadog.flush().await;
+        tracing::info!(flushed, "flushed the Datadog logs on SIGUSR1");
+    }
+}
+
+/// Logs the number of logs waiting to be shipped to Datadog every `interval`.
+async fn report_dd_queue(interval: Duration, datadog: DatadogHandle) {
+    let mut ticks = tokio::time::interval(interval);
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        tracing::info!(depth = datadog.queue_depth(), "dd_queue");
+    }
+}
+
+/// Logs a `heartbeat` every `interval`, so there's a steady signal even without traffic.
+async fn heartbeat(interval: Duration, started_at: Instant) {
+    let mut ticks = tokio::time::interval(interval);
+    // the first tick is immediate, the service just logged that it started
+    ticks.tick().await;
+    loop {
+        ticks.tick().await;
+        tracing::info!(uptime_seconds = started_at.elapsed().as_secs(), "heartbeat");
+    }
+}
+
+/// Milliseconds since `start`, saturating instead of truncating.
+pub fn elapsed_ms(start: Instant) -> u64 {
+    u64::try_from(start.elapsed().as_millis()).unwrap_or(u64::MAX)
+}
+
+/// Worker threads of the tokio runtime we're running on, if any.
+pub fn worker_threads() -> Option<usize> {
+    tokio::runtime::Handle::try_current()
+        .ok()
+        .map(|runtime| runtime.metrics().num_workers())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, Logs, TestState};
+    use shuttle_runtime::Service;
+
+    /// Runs the service on a random port until `MAX_RUNTIME_S` (the clock is paused, so it
+    /// stops as soon as it's idle).
+    async fn run(test: &TestState) -> Result<(), Error> {
+        let service = AppService::new(
+            test.router(),
+            crate::build_admin_router(test.state.clone()),
+            test.state.clone(),
+            Instant::now(),
+        );
+        service.bind(SocketAddr::from(([127, 0, 0, 1], 0))).await
+    }
+
+    /// Starts the service on a free port, returning its address once it's accepting
+    /// connections.
+    async fn start(test: &TestState) -> (SocketAddr, tokio::task::JoinHandle<Result<(), Error>>) {
+        let addr = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let service = AppService::new(test.router(), None, test.state.clone(), Instant::now());
+        let served = tokio::spawn(service.bind(addr));
+        while tokio::net::TcpStream::connect(addr).await.is_err() {
+            tokio::task::yield_now().await;
+        }
+        (addr, served)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn cold_start_is_logged_once_bound() {
+        let (logs, _guard) = Logs::capture();
+        let test = test_support::state(&[("MAX_RUNTIME_S", "1")]);
+
+        run(&test).await.unwrap();
+
+        let cold_start = logs.find("cold_start").expect("no cold_start log");
+        assert!(cold_start["duration_ms"].is_u64());
+        let events = logs.events();
+        let position = |message: &str| events.iter().position(|e| e["message"] == message);
+        assert!(position("cold_start") < position("Server stopped"));
+    }
+
+    #[tokio::test]
+    async fn server_stops_after_max_runtime() {
+        let (logs, _guard) = Logs::capture();
+        let test = test_support::state(&[("MAX_RUNTIME_S", "1")]);
+
+        tokio::time::timeout(Duration::from_secs(10), run(&test))
+            .await
+            .expect("the server didn't stop")
+            .unwrap();
+
+        assert!(logs.find("Max runtime reached, shutting down").is_some());
+        assert!(logs.find("Server stopped").is_some());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_signal_never_fires_without_max_runtime() {
+        let signal = shutdown_signal(None, std::future::pending());
+        let signal = tokio::time::timeout(Duration::from_secs(3600), signal);
+
+        assert!(signal.await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn shutdown_signal_fires_on_stop_before_max_runtime() {
+        let (logs, _guard) = Logs::capture();
+        let signal = shutdown_signal(Some(3600), async {});
+
+        tokio::time::timeout(Duration::from_secs(1), signal)
+            .await
+            .expect("the stop signal was ignored");
+        assert!(logs.find("Stop signal received, shutting down").is_some());
+        assert!(logs.find("Max runtime reached, shutting down").is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn dd_queue_is_reported_when_enabled() {
+        for (interval, reported) in [(Some("1"), true), (None, false)] {
+            let (logs, _guard) = Logs::capture();
+            let mut pairs = vec![("MAX_RUNTIME_S", "3")];
+            pairs.extend(interval.map(|interval| ("DD_QUEUE_REPORT_INTERVAL_S", interval)));
+            let test = test_support::state(&pairs);
+
+            run(&test).await.unwrap();
+
+            let dd_queue = logs.find("dd_queue");
+            assert_eq!(dd_queue.is_some(), reported, "{interval:?}");
+            if let Some(dd_queue) = dd_queue {
+                assert_eq!(dd_queue["depth"], 0);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn http2_cleartext_replaces_http1() {
+        let h2c = reqwest::Client::builder()
+            .http2_prior_knowledge()
+            .build()
+            .unwrap();
+        let http1 = reqwest::Client::new();
+
+        let test = test_support::state(&[("HTTP2_CLEARTEXT", "true")]);
+        let (addr, served) = start(&test).await;
+        let response = h2c
+            .get(format!("http://{addr}/health"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.version(), reqwest::Version::HTTP_2);
+        assert!(http1
+            .get(format!("http://{addr}/health"))
+            .send()
+            .await
+            .is_err());
+        served.abort();
+
+        let test = test_support::state(&[]);
+        let (addr, served) = start(&test).await;
+        let response = http1
+            .get(format!("http://{addr}/health"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.version(), reqwest::Version::HTTP_11);
+        served.abort();
+    }
+
+    #[tokio::test]
+    async fn oversized_headers_are_rejected() {
+        let test = test_support::state(&[("MAX_HEADER_BYTES", "8192")]);
+        let (addr, served) = start(&test).await;
+        let client = reqwest::Client::new();
+        let get = |header_bytes: usize| {
+            client
+                .get(format!("http://{addr}/health"))
+                .header("x-padding", "x".repeat(header_bytes))
+                .send()
+        };
+
+        assert_eq!(get(1024).await.unwrap().status(), 200);
+        assert_eq!(
+            get(16 * 1024).await.unwrap().status(),
+            reqwest::StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE
+        );
+        served.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn heartbeat_is_logged_until_shutdown() {
+        let (logs, _guard) = Logs::capture();
+        let test = test_support::state(&[("HEARTBEAT_INTERVAL_S", "1"), ("MAX_RUNTIME_S", "3")]);
+        let heartbeats = || {
+            logs.events()
+                .into_iter()
+                .filter(|event| event["message"] == "heartbeat")
+                .count()
+        };
+
+        run(&test).await.unwrap();
+        let logged = heartbeats();
+        tokio::time::sleep(Duration::from_secs(10)).await;
+
+        assert!(logged >= 1);
+        assert_eq!(heartbeats(), logged);
+        assert!(logs.find("heartbeat").unwrap()["uptime_seconds"].is_u64());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn logs_are_flushed_on_shutdown() {
+        let secrets = test_support::secrets(&[("MAX_RUNTIME_S", "1")]);
+        let (test, shipped) = TestState::with_datadog(secrets);
+
+        run(&test).await.unwrap();
+
+        let confirmation = shipped
+            .logs
+            .events()
+            .into_iter()
+            .find(|event| {
+                event["message"]
+                    .as_str()
+                    .is_some_and(|m| m.starts_with("flushed "))
+            })
+            .expect("no flush confirmation");
+        let logs = shipped.logs();
+        assert_eq!(confirmation["flushed"], logs.len());
+        assert_eq!(
+            confirmation["message"],
+            format!("flushed {} logs on shutdown", logs.len())
+        );
+        assert!(logs.iter().any(|log| log["message"] == "Server stopped"));
+        assert!(!logs
+            .iter()
+            .any(|log| log["message"] == confirmation["message"]));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn sigusr1_flushes_the_datadog_logs() {
+        use tokio::signal::unix::{signal, SignalKind};
+        // until something listens to it, SIGUSR1 kills the tests
+        let _sigusr1 = signal(SignalKind::user_defined1()).unwrap();
+        let (test, shipped) = TestState::with_datadog(test_support::secrets(&[]));
+        tracing::info!("before the signal");
+
+        let flushing = tokio::spawn(flush_on_sigusr1(test.state.datadog.clone()));
+        // the task listens once it's been polled, so it may miss the first ones
+        while shipped
+            .logs
+            .find("flushed the Datadog logs on SIGUSR1")
+            .is_none()
+        {
+            std::process::Command::new("kill")
+                .args(["-USR1", &std::process::id().to_string()])
+                .status()
+                .unwrap();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        flushing.abort();
+        test.state.datadog.flush().await;
+
+        let flushed = shipped.logs.find("flushed the Datadog logs on SIGUSR1");
+        assert_eq!(flushed.unwrap()["flushed"], 1);
+        assert_eq!(shipped.logs()[0]["message"], "before the signal");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn no_heartbeat_by_default() {
+        let (logs, _guard) = Logs::capture();
+        let test = test_support::state(&[("MAX_RUNTIME_S", "3")]);
+
+        run(&test).await.unwrap();
+
+        assert!(logs.find("heartbeat").is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn slow_startup_warns_only_past_the_slo() {
+        for (startup_slo_ms, warned) in [("100", true), ("60000", false)] {
+            let (logs, _guard) = Logs::capture();
+            let test =
+                test_support::state(&[("MAX_RUNTIME_S", "1"), ("STARTUP_SLO_MS", startup_slo_ms)]);
+            // as if the startup had taken half a second
+            let started_at = Instant::now() - Duration::from_millis(500);
+            let service = AppService::new(test.router(), None, test.state.clone(), started_at);
+
+            service
+                .bind(SocketAddr::from(([127, 0, 0, 1], 0)))
+                .await
+                .unwrap();
+
+            let slow_startup = logs.find("slow startup");
+            assert_eq!(slow_startup.is_some(), warned, "{startup_slo_ms}");
+            if let Some(slow_startup) = slow_startup {
+                assert!(slow_startup["duration_ms"].as_u64() >= Some(500));
+                assert_eq!(slow_startup["level"], "WARN");
+            }
+        }
+    }
+
+    /// A port nothing is listening on.
+    fn free_port() -> u16 {
+        std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    #[tokio::test]
+    async fn admin_endpoints_are_only_served_on_the_admin_port() {
+        let admin_port = free_port().to_string();
+        let test =
+            test_support::state(&[("ADMIN_TOKEN", "admin-token"), ("ADMIN_PORT", &admin_port)]);
+        let addr = SocketAddr::from(([127, 0, 0, 1], free_port()));
+        let admin_addr = format!("127.0.0.1:{admin_port}");
+        let service = AppService::new(
+            test.router(),
+            crate::build_admin_router(test.state.clone()),
+            test.state.clone(),
+            Instant::now(),
+        );
+        let served = tokio::spawn(service.bind(addr));
+        for addr in [addr.to_string(), admin_addr.clone()] {
+            while tokio::net::TcpStream::connect(&addr).await.is_err() {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        let client = reqwest::Client::new();
+        let drain = |addr: String| {
+            client
+                .post(format!("http://{addr}/admin/drain"))
+                .bearer_auth("admin-token")
+                .send()
+        };
+        assert_eq!(drain(addr.to_string()).await.unwrap().status(), 404);
+        assert_eq!(drain(admin_addr.clone()).await.unwrap().status(), 200);
+        let health = client
+            .get(format!("http://{admin_addr}/health"))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(health.status(), 404);
+        served.abort();
+    }
+
+    #[test]
+    fn worker_threads_needs_a_runtime() {
+        assert_eq!(worker_threads(), None);
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        assert_eq!(runtime.block_on(async { worker_threads() }), Some(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn startup_summary_has_the_worker_threads() {
+        let (logs, _guard) = Logs::capture();
+        let test = test_support::state(&[("MAX_RUNTIME_S", "1"), ("STARTUP_LOG_STYLE", "summary")]);
+
+        run(&test).await.unwrap();
+
+        let startup = logs.find("startup").expect("no startup log");
+        assert_eq!(startup["worker_threads"], 1);
+    }
+
+    #[test]
+    fn binding_a_port_in_use_is_diagnosed() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (logs, _guard) = Logs::capture();
+
+        let Err(error) = axum::Server::try_bind(&addr) else {
+            panic!("{addr} bound twice");
+        };
+        assert_eq!(bind_diagnosis(&error), "address_in_use");
+
+        assert!(try_bind(&addr).is_err());
+        let failed = logs.find("failed to bind").expect("no bind failure log");
+        assert_eq!(failed["diagnosis"], "address_in_use");
+        assert_eq!(failed["addr"], addr.to_string());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn summary_startup_log_is_a_single_event() {
+        for (style, logged) in [
+            ("verbose", vec!["cold_start", "admin listener started"]),
+            ("summary", vec!["startup"]),
+        ] {
+            let (logs, _guard) = Logs::capture();
+            let admin_port = free_port().to_string();
+            let test = test_support::state(&[
+                ("MAX_RUNTIME_S", "1"),
+                ("STARTUP_LOG_STYLE", style),
+                ("ADMIN_TOKEN", "admin-token"),
+                ("ADMIN_PORT", &admin_port),
+            ]);
+
+            run(&test).await.unwrap();
+
+            let startup_logs = logs
+                .events()
+                .into_iter()
+                .map(|event| event["message"].as_str().unwrap_or_default().to_owned())
+                .filter(|message| {
+                    ["cold_start", "admin listener started", "startup"].contains(&message.as_str())
+                })
+                .collect::<Vec<_>>();
+            assert_eq!(startup_logs, logged, "{style}");
+        }
+    }
+}
@@ -0,0 +1,324 @@
+use crate::{error::AppError, state::SharedState};
+use axum::{
+    body::{self, Full, HttpBody},
+    extract::{OriginalUri, State},
+    http::{request::Parts, HeaderValue, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use ring::{hmac, rand::SystemRandom};
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub const SIGNATURE_HEADER: &str = "x-response-signature";
+
+pub const ADMIN_TIMESTAMP_HEADER: &str = "x-admin-timestamp";
+pub const ADMIN_NONCE_HEADER: &str = "x-admin-nonce";
+pub const ADMIN_SIGNATURE_HEADER: &str = "x-admin-signature";
+
+/// How far the timestamp of a signed admin request can be from our clock.
+const ADMIN_SIGNATURE_WINDOW_S: u64 = 300;
+/// Nonces remembered at most. Past it, signed requests are rejected until some expire.
+const MAX_NONCES: usize = 4096;
+
+/// Adds an `X-Response-Signature` header with the hex HMAC-SHA256 of the body, keyed with
+/// `RESPONSE_SIGNING_KEY`.
+///
+/// Only bodies with a known size are signed, streaming ones are left untouched.
+pub async fn sign_responses<B>(
+    State(state): State<SharedState>,
+    req: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let response = next.run(req).await;
+    let Some(signing_key) = state.config().response_signing_key else {
+        return response;
+    };
+    if response.body().size_hint().exact().is_none() {
+        return response;
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let bytes = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::error!(error = %e, "Failed to read the response body to sign it");
+            return parts.status.into_response();
+        }
+    };
+    let key = hmac::Key::new(hmac::HMAC_SHA256, signing_key.as_bytes());
+    let signature = hex(hmac::sign(&key, &bytes).as_ref());
+    parts.headers.insert(
+        SIGNATURE_HEADER,
+        HeaderValue::from_str(&signature).expect("hex is a valid header value"),
+    );
+    Response::from_parts(parts, body::boxed(Full::new(bytes)))
+}
+
+/// Nonces of the signed admin requests seen in the last [`ADMIN_SIGNATURE_WINDOW_S`].
+///
+/// Older ones can be forgotten, their timestamp is stale anyway.
+#[derive(Debug, Default)]
+pub struct NonceCache {
+    nonces: Mutex<VecDeque<(String, u64)>>,
+}
+
+impl NonceCache {
+    /// Remembers `nonce`, failing if it was already used or there's no room for it.
+    fn insert(&self, nonce: &str, timestamp: u64, now: u64) -> Result<(), AppError> {
+        let mut nonces = self.nonces.lock().expect("nonces lock poisoned");
+        nonces.retain(|(_, seen_at)| seen_at.abs_diff(now) <= ADMIN_SIGNATURE_WINDOW_S);
+        if nonces.iter().any(|(seen, _)| seen == nonce) {
+            tracing::warn!(nonce, "Admin request replayed");
+            return Err(AppError::Unauthorized);
+        }
+        if nonces.len() >= MAX_NONCES {
+            tracing::warn!("Too many signed admin requests, nonce cache full");
+            return Err(AppError::TooManyRequests);
+        }
+        nonces.push_back((nonce.to_string(), timestamp));
+        Ok(())
+    }
+}
+
+/// Checks an admin request signed with `ADMIN_HMAC_KEY`.
+///
+/// `X-Admin-Signature` is the hex HMAC-SHA256 of `{method}\n{path}\n{timestamp}\n{nonce}\n{body}`,
+/// where the path includes the query and `X-Admin-Timestamp` is in Unix seconds. The
+/// timestamp must be within [`ADMIN_SIGNATURE_WINDOW_S`] and the `X-Admin-Nonce` unused.
+pub fn verify_admin_request(
+    nonces: &NonceCache,
+    key: &str,
+    parts: &Parts,
+    body: &[u8],
+) -> Result<(), AppError> {
+    let header = |name: &str| {
+        parts
+            .headers
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| AppError::BadRequest(format!("missing {name} header")))
+    };
+    let timestamp = header(ADMIN_TIMESTAMP_HEADER)?;
+    let nonce = header(ADMIN_NONCE_HEADER)?;
+    let signature = header(ADMIN_SIGNATURE_HEADER)?;
+    // nested under `/admin`, the request uri lost the prefix
+    let path = parts
+        .extensions
+        .get::<OriginalUri>()
+        .map_or(&parts.uri, |original| &original.0)
+        .path_and_query()
+        .map_or("/", |path| path.as_str());
+
+    let message = [
+        parts.method.as_str().as_bytes(),
+        path.as_bytes(),
+        timestamp.as_bytes(),
+        nonce.as_bytes(),
+        body,
+    ]
+    .join(&b'\n');
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes());
+    let valid =
+        unhex(signature).is_some_and(|signature| hmac::verify(&key, &message, &signature).is_ok());
+    if !valid {
+        tracing::warn!(path, "Invalid admin request signature");
+        return Err(AppError::Unauthorized);
+    }
+
+    let timestamp = timestamp
+        .parse::<u64>()
+        .map_err(|_| AppError::BadRequest(format!("invalid {ADMIN_TIMESTAMP_HEADER} header")))?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    if timestamp.abs_diff(now) > ADMIN_SIGNATURE_WINDOW_S {
+        tracing::warn!(timestamp, now, "Stale admin request signature");
+        return Err(AppError::Unauthorized);
+    }
+    nonces.insert(nonce, timestamp, now)
+}
+
+/// Compares the secrets in constant time, so the response time doesn't tell how much of
+/// `given` matched.
+///
+/// Both are HMAC'd with a random key and the tags compared by `ring`, since its plain
+/// constant-time comparison is deprecated.
+pub fn secrets_match(given: &[u8], expected: &[u8]) -> bool {
+    let Ok(key) = hmac::Key::generate(hmac::HMAC_SHA256, &SystemRandom::new()) else {
+        return false;
+    };
+    let tag = hmac::sign(&key, expected);
+    hmac::verify(&key, given, tag.as_ref()).is_ok()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn unhex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, body_string, send, Logs, TestState};
+    use axum::{body::Body, http::StatusCode, routing::get, Router};
+
+    #[test]
+    fn secrets_match_only_the_same_secret() {
+        assert!(secrets_match(b"admin-token", b"admin-token"));
+        assert!(!secrets_match(b"admin-toke", b"admin-token"));
+        assert!(!secrets_match(b"admin-tokens", b"admin-token"));
+        assert!(!secrets_match(b"", b"admin-token"));
+    }
+
+    /// `/static` answers a fixed body, `/stream` streams it, behind every layer of the app.
+    fn app(test: &TestState) -> Router {
+        let router = Router::new()
+            .route("/static", get(|| async { "signed body" }))
+            .route(
+                "/stream",
+                get(|| async {
+                    let (mut sender, stream) = Body::channel();
+                    tokio::spawn(async move { sender.send_data("streamed body".into()).await });
+                    Response::new(body::boxed(stream))
+                }),
+            );
+        crate::with_layers(router, test.state.clone())
+    }
+
+    fn get_request(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn responses_are_signed_with_the_key() {
+        let test = test_support::state(&[("RESPONSE_SIGNING_KEY", "signing-key")]);
+
+        let response = send(app(&test), get_request("/static")).await;
+
+        let signature = response.headers()[SIGNATURE_HEADER]
+            .to_str()
+            .unwrap()
+            .to_owned();
+        let body = body_string(response).await;
+        assert_eq!(body, "signed body");
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"signing-key");
+        let expected = hmac::sign(&key, body.as_bytes());
+        assert_eq!(unhex(&signature).as_deref(), Some(expected.as_ref()));
+    }
+
+    #[tokio::test]
+    async fn streams_and_unkeyed_responses_are_not_signed() {
+        let test = test_support::state(&[("RESPONSE_SIGNING_KEY", "signing-key")]);
+        let response = send(app(&test), get_request("/stream")).await;
+        assert!(!response.headers().contains_key(SIGNATURE_HEADER));
+        assert_eq!(body_string(response).await, "streamed body");
+
+        let test = test_support::state(&[]);
+        let response = send(app(&test), get_request("/static")).await;
+        assert!(!response.headers().contains_key(SIGNATURE_HEADER));
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+    }
+
+    /// `POST /admin/drain`, signed with `key` at `timestamp`.
+    fn signed_drain(key: &str, timestamp: u64, nonce: &str) -> Request<Body> {
+        let timestamp = timestamp.to_string();
+        let message = format!("POST\n/admin/drain\n{timestamp}\n{nonce}\n");
+        let key = hmac::Key::new(hmac::HMAC_SHA256, key.as_bytes());
+        let signature = hex(hmac::sign(&key, message.as_bytes()).as_ref());
+        Request::post("/admin/drain")
+            .header("authorization", "Bearer admin-token")
+            .header(ADMIN_TIMESTAMP_HEADER, timestamp)
+            .header(ADMIN_NONCE_HEADER, nonce)
+            .header(ADMIN_SIGNATURE_HEADER, signature)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    fn signed_admin() -> TestState {
+        test_support::state(&[
+            ("ADMIN_TOKEN", "admin-token"),
+            ("ADMIN_HMAC_KEY", "hmac-key"),
+        ])
+    }
+
+    #[tokio::test]
+    async fn signed_admin_requests_are_accepted_once() {
+        let test = signed_admin();
+
+        let response = send(test.router(), signed_drain("hmac-key", now(), "nonce-1")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = send(test.router(), signed_drain("hmac-key", now(), "nonce-1")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let response = send(test.router(), signed_drain("hmac-key", now(), "nonce-2")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn stale_or_badly_signed_admin_requests_are_rejected() {
+        let test = signed_admin();
+        let stale = now() - ADMIN_SIGNATURE_WINDOW_S - 10;
+        let future = now() + ADMIN_SIGNATURE_WINDOW_S + 10;
+
+        for (key, timestamp) in [
+            ("hmac-key", stale),
+            ("hmac-key", future),
+            ("wrong-key", now()),
+        ] {
+            let response = send(test.router(), signed_drain(key, timestamp, "nonce")).await;
+            assert_eq!(
+                response.status(),
+                StatusCode::UNAUTHORIZED,
+                "{key} {timestamp}"
+            );
+        }
+        assert!(!test.state.is_draining());
+    }
+
+    #[tokio::test]
+    async fn unsigned_admin_requests_are_rejected() {
+        let test = signed_admin();
+        let req = Request::post("/admin/drain")
+            .header("authorization", "Bearer admin-token")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = send(test.router(), req).await;
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn admin_hmac_key_is_never_logged() {
+        let (logs, _guard) = Logs::capture();
+
+        let _test = signed_admin();
+
+        let resolved = logs
+            .events()
+            .into_iter()
+            .find(|event| event["key"] == "ADMIN_HMAC_KEY")
+            .expect("ADMIN_HMAC_KEY resolution not logged");
+        assert!(resolved.get("value").is_none());
+        assert!(!format!("{:?}", logs.events()).contains("hmac-key"));
+    }
+}
@@ -0,0 +1,151 @@
+use tracing::{Event, Metadata, Subscriber};
+use tracing_subscriber::{
+    layer::{Context, Filter},
+    registry::LookupSpan,
+};
+
+/// Per-layer filter deciding which events are shipped, based on the spans they're in.
+///
+/// A pattern matches a span or event by name, or by target (module path prefix). Events
+/// are dropped if they match the denylist, and kept only if they match the allowlist
+/// (when it isn't empty). Spans are always enabled, otherwise we couldn't see them when
+/// checking their events.
+pub struct SpanFilter {
+    allowlist: Vec<String>,
+    denylist: Vec<String>,
+}
+
+impl SpanFilter {
+    /// Returns `None` when both lists are empty, as there's nothing to filter.
+    pub fn new(allowlist: Vec<String>, denylist: Vec<String>) -> Option<Self> {
+        (!allowlist.is_empty() || !denylist.is_empty()).then_some(Self {
+            allowlist,
+            denylist,
+        })
+    }
+}
+
+impl<S> Filter<S> for SpanFilter
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn enabled(&self, _metadata: &Metadata<'_>, _cx: &Context<'_, S>) -> bool {
+        true
+    }
+
+    fn event_enabled(&self, event: &Event<'_>, cx: &Context<'_, S>) -> bool {
+        let mut allowed = self.allowlist.is_empty();
+        let mut check = |metadata: &Metadata<'_>| {
+            if matches_any(&self.denylist, metadata) {
+                return false;
+            }
+            allowed = allowed || matches_any(&self.allowlist, metadata);
+            true
+        };
+
+        if !check(event.metadata()) {
+            return false;
+        }
+        if let Some(scope) = cx.event_scope(event) {
+            for span in scope {
+                if !check(span.metadata()) {
+                    return false;
+                }
+            }
+        }
+        allowed
+    }
+}
+
+fn matches_any(patterns: &[String], metadata: &Metadata<'_>) -> bool {
+    patterns.iter().any(|pattern| {
+        let target = metadata.target();
+        metadata.name() == pattern
+            || target == pattern
+            || target
+                .strip_prefix(pattern.as_str())
+                .is_some_and(|rest| rest.starts_with("::"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Logs;
+    use tracing_subscriber::{fmt, prelude::*, Registry};
+
+    fn log_requests() {
+        tracing::info_span!("health").in_scope(|| tracing::info!("health checked"));
+        tracing::info_span!("request").in_scope(|| {
+            tracing::info_span!("db_query").in_scope(|| tracing::info!("query run"));
+            tracing::info!("request completed");
+        });
+    }
+
+    /// Messages of the events reaching the filtered (Datadog) and the unfiltered (local)
+    /// layers.
+    fn shipped(allowlist: &[&str], denylist: &[&str]) -> (Vec<String>, Vec<String>) {
+        let list = |patterns: &[&str]| patterns.iter().map(|p| p.to_string()).collect();
+        let (datadog, local) = (Logs::default(), Logs::default());
+        let (datadog_writer, local_writer) = (datadog.clone(), local.clone());
+        let subscriber = Registry::default()
+            .with(
+                fmt::layer()
+                    .json()
+                    .flatten_event(true)
+                    .with_writer(move || datadog_writer.clone())
+                    .with_filter(SpanFilter::new(list(allowlist), list(denylist)).unwrap()),
+            )
+            .with(
+                fmt::layer()
+                    .json()
+                    .flatten_event(true)
+                    .with_writer(move || local_writer.clone()),
+            );
+        tracing::subscriber::with_default(subscriber, log_requests);
+
+        let messages = |logs: Logs| {
+            logs.events()
+                .into_iter()
+                .map(|event| event["message"].as_str().unwrap().to_string())
+                .collect()
+        };
+        (messages(datadog), messages(local))
+    }
+
+    #[test]
+    fn denylisted_spans_are_not_shipped() {
+        let (datadog, local) = shipped(&[], &["health"]);
+
+        assert_eq!(datadog, ["query run", "request completed"]);
+        assert_eq!(local, ["health checked", "query run", "request completed"]);
+    }
+
+    #[test]
+    fn only_allowlisted_spans_are_shipped() {
+        let (datadog, _) = shipped(&["db_query"], &[]);
+
+        assert_eq!(datadog, ["query run"]);
+    }
+
+    #[test]
+    fn denylist_wins_over_allowlist() {
+        let (datadog, _) = shipped(&["request"], &["db_query"]);
+
+        assert_eq!(datadog, ["request completed"]);
+    }
+
+    #[test]
+    fn patterns_match_target_prefixes() {
+        let (datadog, _) = shipped(&[], &["shuttle_datadog_logs"]);
+        assert!(datadog.is_empty());
+
+        let (datadog, _) = shipped(&[], &["shuttle_datadog"]);
+        assert_eq!(datadog.len(), 3);
+    }
+
+    #[test]
+    fn no_filter_without_patterns() {
+        assert!(SpanFilter::new(vec![], vec![]).is_none());
+    }
+}
@@ -0,0 +1,162 @@
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use tracing::{span, subscriber::Interest, Metadata, Subscriber};
+use tracing_subscriber::{
+    layer::{Context, Layer},
+    registry::LookupSpan,
+};
+
+/// Caps the number of spans created under each root span (i.e. per request), so a runaway
+/// handler can't flood Datadog. Events are still recorded, in the closest span kept.
+///
+/// It works as a global filter, like [`crate::sampling::SpanSampler`].
+pub struct SpanLimit {
+    max: usize,
+}
+
+/// Stored in the extensions of the root spans.
+#[derive(Default)]
+struct SpanCount {
+    count: AtomicUsize,
+    warned: AtomicBool,
+}
+
+impl SpanLimit {
+    pub fn new(max: usize) -> Self {
+        Self { max }
+    }
+}
+
+impl<S> Layer<S> for SpanLimit
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+        if metadata.is_span() {
+            // it depends on the current span, so it must be checked every time
+            Interest::sometimes()
+        } else {
+            Interest::always()
+        }
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, ctx: Context<'_, S>) -> bool {
+        if !metadata.is_span() {
+            return true;
+        }
+        let Some(root) = ctx
+            .lookup_current()
+            .and_then(|span| span.scope().from_root().next())
+        else {
+            return true;
+        };
+        let extensions = root.extensions();
+        let Some(count) = extensions.get::<SpanCount>() else {
+            return true;
+        };
+        if count.count.load(Ordering::Relaxed) < self.max {
+            return true;
+        }
+        let first = !count.warned.swap(true, Ordering::Relaxed);
+        // the warning goes through the layers, which may need the extensions
+        drop(extensions);
+        if first {
+            tracing::warn!(
+                max_spans = self.max,
+                root = root.name(),
+                "Span limit reached, no more spans are created for this request"
+            );
+        }
+        false
+    }
+
+    fn on_new_span(&self, _attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+        let Some(span) = ctx.span(id) else {
+            return;
+        };
+        match span.scope().from_root().next() {
+            Some(root) if root.id() != *id => {
+                if let Some(count) = root.extensions().get::<SpanCount>() {
+                    count.count.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            _ => span.extensions_mut().insert(SpanCount::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::Counter;
+    use tracing_subscriber::{prelude::*, Registry};
+
+    /// A request opening `children` spans (nested two levels), with an event in each.
+    fn request(children: usize) {
+        let _request = tracing::info_span!("request").entered();
+        for _ in 0..children / 2 {
+            let _query = tracing::info_span!("query").entered();
+            tracing::info!("querying");
+            let _row = tracing::info_span!("row").entered();
+            tracing::info!("reading");
+        }
+    }
+
+    #[test]
+    fn spans_past_the_limit_are_not_created() {
+        let counter = Counter::default();
+        let subscriber = Registry::default()
+            .with(SpanLimit::new(3))
+            .with(counter.clone());
+
+        tracing::subscriber::with_default(subscriber, || request(10));
+
+        // the root and 3 children
+        assert_eq!(counter.spans(), 4);
+        // the events are all kept
+        assert_eq!(counter.events(), 10);
+    }
+
+    #[test]
+    fn the_limit_is_per_root_span() {
+        let counter = Counter::default();
+        let subscriber = Registry::default()
+            .with(SpanLimit::new(3))
+            .with(counter.clone());
+
+        tracing::subscriber::with_default(subscriber, || {
+            request(10);
+            request(2);
+        });
+
+        assert_eq!(counter.spans(), 4 + 3);
+    }
+
+    #[test]
+    fn the_guard_engages_once_the_limit_is_reached() {
+        let subscriber = Registry::default().with(SpanLimit::new(2));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let request = tracing::info_span!("request");
+            let _entered = request.enter();
+            let count = || {
+                request.with_subscriber(|(id, dispatch)| {
+                    let registry = dispatch.downcast_ref::<Registry>().unwrap();
+                    let span = registry.span(id).unwrap();
+                    let extensions = span.extensions();
+                    let count = extensions.get::<SpanCount>().unwrap();
+                    (
+                        count.count.load(Ordering::Relaxed),
+                        count.warned.load(Ordering::Relaxed),
+                    )
+                })
+            };
+
+            let _first = tracing::info_span!("child").entered();
+            assert_eq!(count(), Some((1, false)));
+            let _second = tracing::info_span!("child").entered();
+            let third = tracing::info_span!("child").entered();
+            assert!(third.is_disabled());
+            assert_eq!(count(), Some((2, true)));
+        });
+    }
+}
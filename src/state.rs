@@ -0,0 +1,160 @@
+use crate::{
+    capture::CaptureBuffer, client::ClientLimiter, config::Config, datadog::DatadogHandle,
+    health::ReadinessCache, secrets::Secrets, signature::NonceCache,
+};
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex, PoisonError, RwLock,
+    },
+    time::{Duration, Instant},
+};
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+pub type LogReloadHandle = reload::Handle<EnvFilter, Registry>;
+
+pub type SharedState = Arc<AppState>;
+
+pub struct AppState {
+    pub config: RwLock<Config>,
+    /// Kept around so the secrets can be re-read by `/admin/reload-secrets`.
+    pub secrets: Secrets,
+    pub log_reload: LogReloadHandle,
+    /// Counters of the Datadog layers, and what flushes them.
+    pub datadog: DatadogHandle,
+    pub captures: CaptureBuffer,
+    pub http_client: reqwest::Client,
+    pub client_limiter: ClientLimiter,
+    /// Set by `/admin/drain`, makes `/ready` fail so no new traffic is routed here.
+    draining: AtomicBool,
+    /// Health checks seen, for `HEALTH_LOG_SAMPLE`.
+    pub health_checks: AtomicU64,
+    pub started_at: Instant,
+    /// Responses answered with a 4xx in the last minute.
+    pub client_errors: RecentCount,
+    /// Responses answered with a 5xx in the last minute.
+    pub server_errors: RecentCount,
+    /// Nonces of the signed admin requests, so they can't be replayed.
+    pub admin_nonces: NonceCache,
+    /// Results of the `READINESS_CHECKS`, shared by the `/ready` probes.
+    pub readiness: ReadinessCache,
+}
+
+impl AppState {
+    pub fn new(
+        config: Config,
+        secrets: Secrets,
+        log_reload: LogReloadHandle,
+        datadog: DatadogHandle,
+        started_at: Instant,
+    ) -> SharedState {
+        Arc::new(Self {
+            captures: CaptureBuffer::new(config.capture_requests),
+            config: RwLock::new(config),
+            secrets,
+            log_reload,
+            datadog,
+            http_client: reqwest::Client::new(),
+            client_limiter: ClientLimiter::default(),
+            draining: AtomicBool::new(false),
+            health_checks: AtomicU64::new(0),
+            started_at,
+            client_errors: RecentCount::default(),
+            server_errors: RecentCount::default(),
+            admin_nonces: NonceCache::default(),
+            readiness: ReadinessCache::default(),
+        })
+    }
+
+    pub fn config(&self) -> Config {
+        self.config.read().expect("config lock poisoned").clone()
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Returns whether it was already draining.
+    pub fn drain(&self) -> bool {
+        self.draining.swap(true, Ordering::Relaxed)
+    }
+}
+
+/// How far back [`RecentCount`] counts.
+const RECENT: Duration = Duration::from_secs(60);
+
+/// Counts what happened in the last minute, per second.
+pub struct RecentCount {
+    started_at: Instant,
+    /// Second since `started_at` and count of each of the last 60 seconds.
+    seconds: Mutex<[(u64, u64); RECENT.as_secs() as usize]>,
+}
+
+impl Default for RecentCount {
+    fn default() -> Self {
+        Self {
+            started_at: Instant::now(),
+            seconds: Mutex::new([(0, 0); RECENT.as_secs() as usize]),
+        }
+    }
+}
+
+impl RecentCount {
+    pub fn add(&self) {
+        self.add_at(Instant::now());
+    }
+
+    /// How many were added in the last minute.
+    pub fn get(&self) -> u64 {
+        self.get_at(Instant::now())
+    }
+
+    fn second(&self, at: Instant) -> u64 {
+        at.saturating_duration_since(self.started_at).as_secs()
+    }
+
+    fn add_at(&self, at: Instant) {
+        let second = self.second(at);
+        let mut seconds = self.seconds.lock().unwrap_or_else(PoisonError::into_inner);
+        let slot = &mut seconds[(second % RECENT.as_secs()) as usize];
+        // a minute old, or more
+        if slot.0 != second {
+            *slot = (second, 0);
+        }
+        slot.1 += 1;
+    }
+
+    fn get_at(&self, at: Instant) -> u64 {
+        let now = self.second(at);
+        let seconds = self.seconds.lock().unwrap_or_else(PoisonError::into_inner);
+        seconds
+            .iter()
+            .filter(|(second, _)| now - second < RECENT.as_secs())
+            .map(|(_, count)| count)
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recent_count_forgets_what_is_a_minute_old() {
+        let count = RecentCount::default();
+        let start = count.started_at;
+        let at = |secs| start + Duration::from_secs(secs);
+
+        count.add_at(at(0));
+        count.add_at(at(0));
+        count.add_at(at(30));
+        assert_eq!(count.get_at(at(30)), 3);
+        assert_eq!(count.get_at(at(59)), 3);
+        assert_eq!(count.get_at(at(60)), 1);
+
+        // reusing the slot of second 30
+        count.add_at(at(90));
+        assert_eq!(count.get_at(at(90)), 1);
+        assert_eq!(count.get_at(at(200)), 0);
+    }
+}
@@ -0,0 +1,535 @@
+use crate::{
+    cloudevents::CloudEventFormat,
+    config::Config,
+    datadog::{self, DatadogHandle},
+    log_budget::{self, LogBudget},
+    sampling::SpanSampler,
+    span_filter::SpanFilter,
+    span_limit::SpanLimit,
+    state::LogReloadHandle,
+};
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_subscriber::{
+    filter::{self, FilterFn, LevelFilter},
+    fmt::{
+        self,
+        format::{FormatEvent, FormatFields, Writer},
+        FmtContext, MakeWriter,
+    },
+    prelude::*,
+    registry::LookupSpan,
+    reload, EnvFilter, Layer,
+};
+
+/// Version of the shape of the logs we ship, sent as the `log_schema_version` field of every
+/// log.
+///
+/// Bump it whenever fields are added, renamed or removed, so downstream parsers can tell.
+pub const LOG_SCHEMA_VERSION: u32 = 1;
+
+/// Shape of the stdout logs. Datadog always gets its own JSON payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Json,
+    CloudEvents,
+}
+
+impl LogFormat {
+    pub fn parse(format: &str) -> anyhow::Result<Self> {
+        match format.trim().to_lowercase().as_str() {
+            "json" => Ok(Self::Json),
+            "cloudevents" => Ok(Self::CloudEvents),
+            _ => anyhow::bail!("invalid LOG_FORMAT: {format}"),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::CloudEvents => "cloudevents",
+        }
+    }
+}
+
+/// Starts the tracing subscriber, returning the handles to reload the log level and on the
+/// Datadog layer.
+///
+/// If a global subscriber is already set, it's kept: reloading through the returned handle
+/// fails, and the Datadog one has nothing to flush.
+pub fn init(config: &Config) -> (LogReloadHandle, DatadogHandle) {
+    let service_name = normalize_service_name(&config.dd_service);
+
+    // datadog tracing layer
+    let (dd_layer, datadog) =
+        datadog_layers(config, &service_name, &datadog::intake_url(config.region));
+    let dd_layer = dd_layer
+        // behind the same filter, so only what's shipped is counted
+        .and_then(config.log_budget_per_min.map(LogBudget::new))
+        // only the interesting spans are shipped
+        .with_filter(SpanFilter::new(
+            config.dd_span_allowlist.clone(),
+            config.dd_span_denylist.clone(),
+        ))
+        // shipping the budget warnings would make it worse, and the shipping failures would
+        // fail the same way
+        .with_filter(filter::filter_fn(|metadata| {
+            ![log_budget::TARGET, datadog::TARGET].contains(&metadata.target())
+        }));
+
+    // filter layer (reloadable so the log level can be changed at runtime)
+    let env_filter = EnvFilter::try_new(&config.log_level).expect("failed to set log level");
+    let max_level = env_filter.max_level_hint();
+    let (filter_layer, log_reload) = reload::Layer::new(env_filter);
+
+    // span sampling, only needed when not every span is kept
+    let hello_sampler = (config.hello_span_sample < 1.0)
+        .then(|| SpanSampler::new("hello_world", config.hello_span_sample));
+
+    // cap on the spans of a single request
+    let span_limit = config.max_spans_per_request.map(SpanLimit::new);
+
+    // format layer
+    let fmt_layer = build_fmt_layer(
+        std::io::stdout,
+        config.log_format,
+        &service_name,
+        config.log_location,
+        config.logger_prefix.as_deref(),
+    );
+
+    // starting the tracing subscriber
+    tracing_subscriber::registry()
+        .with(filter_layer)
+        .with(hello_sampler)
+        .with(span_limit)
+        .with(fmt_layer)
+        .with(dd_layer)
+        .try_init()
+        // e.g. a test harness already set one, ours would panic
+        .unwrap_or_else(|e| {
+            tracing::warn!(error = %e, "A tracing subscriber was already set, keeping it");
+        });
+
+    for conflict in filter_conflicts(config, max_level) {
+        if max_level.is_none_or(|max_level| max_level >= LevelFilter::WARN) {
+            tracing::warn!(conflict, "Log filters conflict");
+        } else {
+            // the warning would be filtered out too
+            eprintln!("Log filters conflict: {conflict}");
+        }
+    }
+
+    if service_name != config.dd_service {
+        tracing::warn!(
+            dd_service = config.dd_service,
+            service_name,
+            "DD_SERVICE isn't a valid Datadog service name, normalized it"
+        );
+    }
+
+    (log_reload, datadog)
+}
+
+/// Best-effort detection of filter combinations leaving (almost) nothing logged.
+///
+/// `max_level` is the hint of the global `EnvFilter`.
+fn filter_conflicts(config: &Config, max_level: Option<LevelFilter>) -> Vec<String> {
+    let mut conflicts = Vec::new();
+    match max_level {
+        Some(LevelFilter::OFF) => {
+            conflicts.push(format!(
+                "LOG_LEVEL={} turns every log off",
+                config.log_level
+            ));
+        }
+        Some(max_level) if max_level < LevelFilter::INFO => conflicts.push(format!(
+            "LOG_LEVEL={} only keeps {max_level} and above, the request logs are INFO",
+            config.log_level
+        )),
+        _ => {}
+    }
+    let allowlist = &config.dd_span_allowlist;
+    if !allowlist.is_empty()
+        && allowlist
+            .iter()
+            .all(|allowed| config.dd_span_denylist.contains(allowed))
+    {
+        conflicts.push(
+            "every DD_SPAN_ALLOWLIST entry is in DD_SPAN_DENYLIST, nothing is shipped to Datadog"
+                .to_string(),
+        );
+    }
+    conflicts
+}
+
+/// Ships the logs to `intake_url`. The error events go through their own ingestor when
+/// `DD_ERROR_TAGS` is set, so they get the extra tags.
+pub fn datadog_layers<S>(
+    config: &Config,
+    service_name: &str,
+    intake_url: &str,
+) -> (impl Layer<S>, DatadogHandle)
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    let mut handle = DatadogHandle::default();
+    let mut datadog_layer = |tags: &str| {
+        let ingestor = datadog::Ingestor::new(intake_url, &config.dd_api_key, service_name, tags)
+            .with_compression(config.dd_compression);
+        handle
+            .layer(ingestor)
+            .with_location(config.log_location)
+            .with_target_prefix(config.logger_prefix.clone())
+    };
+    let error_layer = config.dd_error_tags.as_ref().map(|error_tags| {
+        datadog_layer(&format!("{},{error_tags}", config.tags)).with_filter(errors_only(true))
+    });
+    let layer = datadog_layer(&config.tags)
+        .with_filter(error_layer.is_some().then(|| errors_only(false)))
+        .and_then(error_layer);
+    (layer, handle)
+}
+
+/// Lets through either only the `ERROR` events or everything else. Spans always go through,
+/// both sides need them for the context.
+fn errors_only(errors: bool) -> FilterFn<impl Fn(&Metadata<'_>) -> bool> {
+    filter::filter_fn(move |metadata| {
+        !metadata.is_event() || (*metadata.level() == Level::ERROR) == errors
+    })
+}
+
+/// Makes `name` a valid Datadog service name: lowercase, at most 100 characters, and only
+/// alphanumerics, `_`, `-`, `:`, `.` and `/`. Anything else becomes a `-`.
+pub fn normalize_service_name(name: &str) -> String {
+    name.trim()
+        .to_lowercase()
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | '0'..='9' | '_' | '-' | ':' | '.' | '/' => c,
+            _ => '-',
+        })
+        .take(100)
+        .collect()
+}
+
+/// Adds the `log_schema_version` field in front of the JSON objects written by the inner
+/// format, and the `target_prefix` to their target.
+struct SchemaVersioned<F> {
+    format: F,
+    target_prefix: Option<String>,
+}
+
+impl<S, N, F> FormatEvent<S, N> for SchemaVersioned<F>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    N: for<'a> FormatFields<'a> + 'static,
+    F: FormatEvent<S, N>,
+{
+    fn format_event(
+        &self,
+        ctx: &FmtContext<'_, S, N>,
+        mut writer: Writer<'_>,
+        event: &Event<'_>,
+    ) -> std::fmt::Result {
+        let mut line = String::new();
+        self.format
+            .format_event(ctx, Writer::new(&mut line), event)?;
+        if let Some(prefix) = &self.target_prefix {
+            // the values are escaped, this can only be the key
+            line = line.replacen("\"target\":\"", &format!("\"target\":\"{prefix}::"), 1);
+        }
+        match line.strip_prefix('{') {
+            Some(rest) => write!(
+                writer,
+                "{{\"log_schema_version\":{LOG_SCHEMA_VERSION},{rest}"
+            ),
+            None => writer.write_str(&line),
+        }
+    }
+}
+
+/// Format layer shared by every non-Datadog output, so the shape is the same whatever the
+/// writer is. `location` adds the file and line of the caller, `source` is the CloudEvents
+/// source, and `target_prefix` is prepended to the targets.
+pub fn build_fmt_layer<S, W>(
+    writer: W,
+    format: LogFormat,
+    source: &str,
+    location: bool,
+    target_prefix: Option<&str>,
+) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    match format {
+        LogFormat::Json => fmt::layer()
+            .with_writer(writer)
+            .with_ansi(true)
+            .fmt_fields(fmt::format::JsonFields::new())
+            .event_format(SchemaVersioned {
+                format: fmt::format()
+                    .with_timer(fmt::time::UtcTime::rfc_3339())
+                    .json()
+                    .flatten_event(true)
+                    .with_target(true)
+                    .with_file(location)
+                    .with_line_number(location)
+                    .with_span_list(true),
+                target_prefix: target_prefix.map(str::to_string),
+            })
+            .boxed(),
+        LogFormat::CloudEvents => fmt::layer()
+            .with_writer(writer)
+            .event_format(
+                CloudEventFormat::new(source)
+                    .with_location(location)
+                    .with_target_prefix(target_prefix),
+            )
+            .boxed(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, Logs, TestState};
+    use serde_json::Value;
+    use tracing_subscriber::Registry;
+
+    /// The events logged by `f`, written by [`build_fmt_layer`].
+    fn format(f: impl FnOnce()) -> Vec<Value> {
+        let logs = Logs::default();
+        let writer = logs.clone();
+        let layer = build_fmt_layer(move || writer.clone(), LogFormat::Json, "test", false, None);
+        tracing::subscriber::with_default(Registry::default().with(layer), f);
+        logs.events()
+    }
+
+    fn log_in_span() {
+        tracing::info_span!("request", request_id = "abc").in_scope(|| {
+            tracing::info!(status = 200, "request completed");
+        });
+    }
+
+    #[test]
+    fn json_events_are_flattened() {
+        let events = format(log_in_span);
+
+        let event = &events[0];
+        assert_eq!(event["message"], "request completed");
+        assert_eq!(event["status"], 200);
+        assert_eq!(event["level"], "INFO");
+        assert_eq!(event["target"], module_path!());
+        assert_eq!(event["spans"][0]["name"], "request");
+        assert_eq!(event["spans"][0]["request_id"], "abc");
+        assert!(event["timestamp"]
+            .as_str()
+            .is_some_and(|timestamp| timestamp.ends_with('Z')));
+    }
+
+    #[test]
+    fn every_writer_gets_the_same_json() {
+        let (console, file) = (Logs::default(), Logs::default());
+        let (console_writer, file_writer) = (console.clone(), file.clone());
+        let subscriber = Registry::default()
+            .with(build_fmt_layer(
+                move || console_writer.clone(),
+                LogFormat::Json,
+                "test",
+                false,
+                None,
+            ))
+            .with(build_fmt_layer(
+                move || file_writer.clone(),
+                LogFormat::Json,
+                "test",
+                false,
+                None,
+            ));
+
+        tracing::subscriber::with_default(subscriber, log_in_span);
+
+        // each layer takes its own timestamp, only its format is the same
+        let without_timestamp = |logs: &Logs| {
+            let mut events = logs.events();
+            for event in &mut events {
+                let timestamp = event.as_object_mut().unwrap().remove("timestamp");
+                assert!(timestamp.is_some_and(|timestamp| timestamp.is_string()));
+            }
+            events
+        };
+        assert_eq!(console.events().len(), 1);
+        assert_eq!(without_timestamp(&console), without_timestamp(&file));
+    }
+
+    #[test]
+    fn every_log_has_the_log_schema_version() {
+        let events = format(log_in_span);
+
+        assert_eq!(events[0]["log_schema_version"], LOG_SCHEMA_VERSION);
+    }
+
+    #[test]
+    fn targets_get_the_logger_prefix() {
+        for format in [LogFormat::Json, LogFormat::CloudEvents] {
+            let logs = Logs::default();
+            let writer = logs.clone();
+            let layer = build_fmt_layer(
+                move || writer.clone(),
+                format,
+                "test",
+                false,
+                Some("billing"),
+            );
+            tracing::subscriber::with_default(Registry::default().with(layer), log_in_span);
+
+            let event = &logs.events()[0];
+            let target = match format {
+                LogFormat::Json => &event["target"],
+                LogFormat::CloudEvents => &event["data"]["target"],
+            };
+            assert_eq!(
+                target,
+                &format!("billing::{}", module_path!()),
+                "{format:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn location_is_only_logged_when_enabled() {
+        for location in [true, false] {
+            let logs = Logs::default();
+            let writer = logs.clone();
+            let layer = build_fmt_layer(
+                move || writer.clone(),
+                LogFormat::Json,
+                "test",
+                location,
+                None,
+            );
+            tracing::subscriber::with_default(Registry::default().with(layer), log_in_span);
+
+            let event = &logs.events()[0];
+            if location {
+                assert_eq!(event["filename"], file!());
+                assert!(event["line_number"].is_u64());
+            } else {
+                assert!(event.get("filename").is_none());
+                assert!(event.get("line_number").is_none());
+            }
+        }
+    }
+
+    #[test]
+    fn service_names_are_normalized() {
+        assert_eq!(normalize_service_name("my-service"), "my-service");
+        assert_eq!(normalize_service_name(" My Service "), "my-service");
+        assert_eq!(normalize_service_name("API_v2"), "api_v2");
+        assert_eq!(normalize_service_name("svc@home!"), "svc-home-");
+        assert_eq!(normalize_service_name("team/svc:1.0"), "team/svc:1.0");
+        assert_eq!(normalize_service_name(&"a".repeat(150)).len(), 100);
+    }
+
+    #[tokio::test]
+    async fn error_events_get_the_error_tags() {
+        let secrets = test_support::secrets(&[("DD_ERROR_TAGS", "index:errors")]);
+        let (test, shipped) = TestState::with_datadog(secrets);
+
+        tracing::error!("failed");
+        tracing::info!("succeeded");
+        test.state.datadog.flush().await;
+
+        let logs = shipped.logs();
+        let tags = |message: &str| {
+            let log = logs
+                .iter()
+                .find(|log| log["message"] == message)
+                .unwrap_or_else(|| panic!("{message} wasn't shipped"));
+            log["ddtags"].as_str().unwrap().to_owned()
+        };
+        assert!(tags("failed").ends_with(",index:errors"));
+        assert!(!tags("succeeded").contains("index:errors"));
+    }
+
+    #[test]
+    fn init_keeps_an_existing_subscriber() {
+        // the global subscriber of the tests calling `init`, it drops everything
+        let _ =
+            tracing::subscriber::set_global_default(tracing::subscriber::NoSubscriber::default());
+        let config = Config::from_secrets(&test_support::secrets(&[])).unwrap();
+        let (logs, _guard) = Logs::capture();
+
+        init(&config);
+        init(&config);
+
+        let warnings = logs
+            .events()
+            .into_iter()
+            .filter(|event| event["message"] == "A tracing subscriber was already set, keeping it")
+            .count();
+        assert_eq!(warnings, 2);
+    }
+
+    fn conflicts(pairs: &[(&str, &str)]) -> Vec<String> {
+        let config = Config::from_secrets(&test_support::secrets(pairs)).unwrap();
+        let max_level = EnvFilter::try_new(&config.log_level)
+            .unwrap()
+            .max_level_hint();
+        filter_conflicts(&config, max_level)
+    }
+
+    #[test]
+    fn conflicting_filters_are_detected() {
+        assert_eq!(
+            conflicts(&[("LOG_LEVEL", "error")]),
+            ["LOG_LEVEL=error only keeps error and above, the request logs are INFO"]
+        );
+        assert_eq!(
+            conflicts(&[("LOG_LEVEL", "off")]),
+            ["LOG_LEVEL=off turns every log off"]
+        );
+        assert_eq!(
+            conflicts(&[
+                ("DD_SPAN_ALLOWLIST", "request"),
+                ("DD_SPAN_DENYLIST", "request,hello_world"),
+            ]),
+            ["every DD_SPAN_ALLOWLIST entry is in DD_SPAN_DENYLIST, nothing is shipped to Datadog"]
+        );
+    }
+
+    #[test]
+    fn consistent_filters_have_no_conflict() {
+        assert!(conflicts(&[]).is_empty());
+        assert!(conflicts(&[("LOG_LEVEL", "debug")]).is_empty());
+        assert!(conflicts(&[
+            ("DD_SPAN_ALLOWLIST", "request,hello_world"),
+            ("DD_SPAN_DENYLIST", "request"),
+        ])
+        .is_empty());
+    }
+
+    #[test]
+    fn init_warns_about_the_conflicts() {
+        // see `init_keeps_an_existing_subscriber`
+        let _ =
+            tracing::subscriber::set_global_default(tracing::subscriber::NoSubscriber::default());
+        let config = Config::from_secrets(&test_support::secrets(&[
+            ("DD_SPAN_ALLOWLIST", "request"),
+            ("DD_SPAN_DENYLIST", "request"),
+        ]))
+        .unwrap();
+        let (logs, _guard) = Logs::capture();
+
+        init(&config);
+
+        let warning = logs
+            .find("Log filters conflict")
+            .expect("no conflict warning");
+        assert_eq!(warning["level"], "WARN");
+        assert!(warning["conflict"]
+            .as_str()
+            .is_some_and(|conflict| conflict.starts_with("every DD_SPAN_ALLOWLIST entry")));
+    }
+}
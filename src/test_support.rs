@@ -0,0 +1,297 @@
+//! Helpers shared by the unit tests.
+
+use crate::{
+    config::Config,
+    datadog::{self, DatadogHandle, Log},
+    secrets::Secrets,
+    state::{AppState, SharedState},
+    telemetry,
+};
+use axum::{
+    body::{Body, BoxBody},
+    extract::State,
+    http::{header, HeaderMap, Request, Response, StatusCode},
+    routing::{get, post},
+    Router,
+};
+use flate2::read::{DeflateDecoder, GzDecoder};
+use serde_json::Value;
+use shuttle_secrets::SecretStore;
+use std::{
+    collections::BTreeMap,
+    io::{self, Read},
+    net::{SocketAddr, TcpListener},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, PoisonError,
+    },
+    time::Instant,
+};
+use tower::ServiceExt;
+use tracing::{span, subscriber::DefaultGuard, Event, Subscriber};
+use tracing_subscriber::{
+    filter, layer::Context, prelude::*, registry::LookupSpan, reload, EnvFilter, Layer, Registry,
+};
+
+/// `DD_API_KEY` used unless the test sets one.
+pub const API_KEY: &str = "test-api-key";
+
+/// Shuttle secrets with `pairs`, and [`API_KEY`] as the `DD_API_KEY`.
+pub fn secrets(pairs: &[(&str, &str)]) -> Secrets {
+    let mut secrets = BTreeMap::from([("DD_API_KEY".to_string(), API_KEY.to_string())]);
+    secrets.extend(
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string())),
+    );
+    Secrets::new(SecretStore::new(secrets))
+}
+
+/// The state of the app, and the subscriber its log level can be reloaded on.
+pub struct TestState {
+    pub state: SharedState,
+    // the reload handle only works while the layer is alive
+    _subscriber: Box<dyn Subscriber + Send + Sync>,
+}
+
+impl TestState {
+    pub fn new(secrets: Secrets) -> Self {
+        let config = Config::from_secrets(&secrets).expect("invalid test config");
+        let filter = EnvFilter::try_new(&config.log_level).expect("invalid test log level");
+        let (filter_layer, log_reload) = reload::Layer::<_, Registry>::new(filter);
+        Self {
+            state: AppState::new(
+                config,
+                secrets,
+                log_reload,
+                DatadogHandle::default(),
+                Instant::now(),
+            ),
+            _subscriber: Box::new(Registry::default().with(filter_layer)),
+        }
+    }
+
+    /// Same as [`TestState::new`], but the logs of the current thread are shipped with the
+    /// layers of `telemetry::datadog_layers` to a [`mock_intake`] accepting everything, and
+    /// captured as [`Logs`] too, until the guard is dropped.
+    ///
+    /// Flush before dropping the guard: the layers wait for their pending logs to be sent,
+    /// and the mock intake can't answer while the test is blocked on it.
+    pub fn with_datadog(secrets: Secrets) -> (Self, Shipped) {
+        let config = Config::from_secrets(&secrets).expect("invalid test config");
+        let filter = EnvFilter::try_new(&config.log_level).expect("invalid test log level");
+        let (filter_layer, log_reload) = reload::Layer::new(filter);
+        let (intake_url, batches) = mock_intake(|_| StatusCode::ACCEPTED);
+        let service_name = telemetry::normalize_service_name(&config.dd_service);
+        let (dd_layer, datadog) = telemetry::datadog_layers(&config, &service_name, &intake_url);
+        let logs = Logs::default();
+        let subscriber = Registry::default()
+            .with(filter_layer)
+            .with(logs.layer())
+            .with(dd_layer.with_filter(filter::filter_fn(|metadata| {
+                metadata.target() != datadog::TARGET
+            })));
+        let test = Self {
+            state: AppState::new(config, secrets, log_reload, datadog, Instant::now()),
+            _subscriber: Box::new(Registry::default()),
+        };
+        let shipped = Shipped {
+            batches,
+            logs,
+            _guard: tracing::subscriber::set_default(subscriber),
+        };
+        (test, shipped)
+    }
+
+    /// The public router, with every layer.
+    pub fn router(&self) -> Router {
+        crate::build_router(self.state.clone())
+    }
+}
+
+pub fn state(pairs: &[(&str, &str)]) -> TestState {
+    TestState::new(secrets(pairs))
+}
+
+/// Sends `req` to `router`.
+pub async fn send(router: Router, req: Request<Body>) -> Response<BoxBody> {
+    router.oneshot(req).await.expect("infallible")
+}
+
+pub async fn body_string(response: Response<BoxBody>) -> String {
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    String::from_utf8(body.to_vec()).unwrap()
+}
+
+/// Serves `app` on a random local port, returning its base URL.
+pub fn serve(app: Router) -> String {
+    let listener = TcpListener::bind(SocketAddr::from(([127, 0, 0, 1], 0))).unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(app.into_make_service()),
+    );
+    format!("http://{addr}")
+}
+
+/// A fake Datadog `/api/v1/validate` that only accepts `valid-key`, and fails with
+/// `broken-key`.
+pub fn mock_datadog() -> String {
+    async fn validate(headers: HeaderMap) -> StatusCode {
+        match headers.get("DD-API-KEY").and_then(|k| k.to_str().ok()) {
+            Some("valid-key") => StatusCode::OK,
+            Some("broken-key") => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::FORBIDDEN,
+        }
+    }
+
+    serve(Router::new().route("/api/v1/validate", get(validate)))
+}
+
+/// A batch received by [`mock_intake`].
+pub struct Batch {
+    pub content_encoding: Option<String>,
+    pub logs: Vec<Log>,
+}
+
+/// Batches received by [`mock_intake`].
+pub type Batches = Arc<Mutex<Vec<Batch>>>;
+/// How [`mock_intake`] answers a batch.
+pub type Answer = fn(&[Log]) -> StatusCode;
+
+/// A fake Datadog logs intake, returning its URL and the batches it got.
+pub fn mock_intake(answer: Answer) -> (String, Batches) {
+    async fn intake(
+        State((batches, answer)): State<(Batches, Answer)>,
+        headers: HeaderMap,
+        body: axum::body::Bytes,
+    ) -> StatusCode {
+        let content_encoding = headers
+            .get(header::CONTENT_ENCODING)
+            .map(|value| value.to_str().unwrap().to_string());
+        let mut json = Vec::new();
+        match content_encoding.as_deref() {
+            None => json.extend_from_slice(&body),
+            Some("gzip") => GzDecoder::new(&body[..])
+                .read_to_end(&mut json)
+                .map(drop)
+                .unwrap(),
+            Some("deflate") => DeflateDecoder::new(&body[..])
+                .read_to_end(&mut json)
+                .map(drop)
+                .unwrap(),
+            Some(encoding) => panic!("unexpected Content-Encoding: {encoding}"),
+        }
+        let logs: Vec<Log> = serde_json::from_slice(&json).expect("invalid logs payload");
+        let status = answer(&logs);
+        batches.lock().unwrap().push(Batch {
+            content_encoding,
+            logs,
+        });
+        status
+    }
+
+    let batches = Batches::default();
+    let app = Router::new()
+        .route("/api/v2/logs", post(intake))
+        .with_state((batches.clone(), answer));
+    (format!("{}/api/v2/logs", serve(app)), batches)
+}
+
+/// What [`TestState::with_datadog`] got.
+pub struct Shipped {
+    pub batches: Batches,
+    pub logs: Logs,
+    _guard: DefaultGuard,
+}
+
+impl Shipped {
+    /// Every log the mock intake got.
+    pub fn logs(&self) -> Vec<Log> {
+        let batches = self.batches.lock().unwrap();
+        batches
+            .iter()
+            .flat_map(|batch| batch.logs.clone())
+            .collect()
+    }
+}
+
+/// Logs written by the current thread, as JSON lines.
+#[derive(Clone, Default)]
+pub struct Logs(Arc<Mutex<Vec<u8>>>);
+
+impl Logs {
+    /// Captures every log of the current thread until the guard is dropped.
+    pub fn capture() -> (Self, DefaultGuard) {
+        let logs = Self::default();
+        let subscriber = Registry::default().with(logs.layer());
+        (logs, tracing::subscriber::set_default(subscriber))
+    }
+
+    /// Layer writing every log to these.
+    fn layer<S>(&self) -> impl Layer<S>
+    where
+        S: Subscriber + for<'a> LookupSpan<'a>,
+    {
+        let writer = self.clone();
+        tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_writer(move || writer.clone())
+    }
+
+    pub fn events(&self) -> Vec<Value> {
+        let bytes = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        String::from_utf8_lossy(&bytes)
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("invalid JSON log"))
+            .collect()
+    }
+
+    /// The first event with the given `message`.
+    pub fn find(&self, message: &str) -> Option<Value> {
+        self.events()
+            .into_iter()
+            .find(|event| event["message"] == message)
+    }
+}
+
+impl io::Write for Logs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut bytes = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Layer counting the spans created and the events recorded.
+#[derive(Clone, Default)]
+pub struct Counter {
+    spans: Arc<AtomicU64>,
+    events: Arc<AtomicU64>,
+}
+
+impl Counter {
+    pub fn spans(&self) -> u64 {
+        self.spans.load(Ordering::Relaxed)
+    }
+
+    pub fn events(&self) -> u64 {
+        self.events.load(Ordering::Relaxed)
+    }
+}
+
+impl<S: Subscriber> Layer<S> for Counter {
+    fn on_new_span(&self, _: &span::Attributes<'_>, _: &span::Id, _: Context<'_, S>) {
+        self.spans.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_event(&self, _: &Event<'_>, _: Context<'_, S>) {
+        self.events.fetch_add(1, Ordering::Relaxed);
+    }
+}
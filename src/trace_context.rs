@@ -0,0 +1,120 @@
+//! W3C trace context (`traceparent`), see <https://www.w3.org/TR/trace-context/>.
+
+use axum::http::{HeaderMap, HeaderValue};
+use std::fmt;
+
+pub const TRACEPARENT: &str = "traceparent";
+
+/// The `traceparent` of a request handled by this service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceParent {
+    pub trace_id: u128,
+    pub parent_id: u64,
+    pub sampled: bool,
+}
+
+impl TraceParent {
+    /// Continues the caller's trace if it sent a valid `traceparent`, or starts a new one.
+    pub fn for_request(headers: &HeaderMap) -> Self {
+        let incoming = headers
+            .get(TRACEPARENT)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Self::parse);
+        Self {
+            trace_id: incoming.map_or_else(new_trace_id, |parent| parent.trace_id),
+            parent_id: new_parent_id(),
+            sampled: incoming.is_none_or(|parent| parent.sampled),
+        }
+    }
+
+    /// Parses a version `00` header. Other versions and all-zero ids are invalid.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = value.trim().split('-');
+        let (version, trace_id, parent_id, flags) =
+            (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+        if version != "00" || parts.next().is_some() {
+            return None;
+        }
+        if trace_id.len() != 32 || parent_id.len() != 16 || flags.len() != 2 {
+            return None;
+        }
+        let trace_id = u128::from_str_radix(trace_id, 16)
+            .ok()
+            .filter(|id| *id != 0)?;
+        let parent_id = u64::from_str_radix(parent_id, 16)
+            .ok()
+            .filter(|id| *id != 0)?;
+        let flags = u8::from_str_radix(flags, 16).ok()?;
+        Some(Self {
+            trace_id,
+            parent_id,
+            sampled: flags & 1 == 1,
+        })
+    }
+
+    pub fn trace_id_hex(&self) -> String {
+        format!("{:032x}", self.trace_id)
+    }
+
+    pub fn to_header_value(self) -> HeaderValue {
+        HeaderValue::from_str(&self.to_string()).expect("traceparent is a valid header value")
+    }
+}
+
+impl fmt::Display for TraceParent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "00-{:032x}-{:016x}-{:02x}",
+            self.trace_id,
+            self.parent_id,
+            u8::from(self.sampled)
+        )
+    }
+}
+
+fn new_trace_id() -> u128 {
+    // zero is not a valid id
+    rand::random::<u128>().max(1)
+}
+
+fn new_parent_id() -> u64 {
+    rand::random::<u64>().max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn traceparent_round_trips() {
+        let value = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let trace_parent = TraceParent::parse(value).expect("valid traceparent");
+        assert_eq!(trace_parent.to_string(), value);
+        assert!(trace_parent.sampled);
+    }
+
+    #[test]
+    fn invalid_traceparents_are_rejected() {
+        for value in [
+            "",
+            "01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-00",
+            "00-4bf92f3577b34da6a3ce929d0e0e47-00f067aa0ba902b7-01",
+            "00-zzf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        ] {
+            assert_eq!(TraceParent::parse(value), None, "{value}");
+        }
+    }
+
+    #[test]
+    fn new_traces_are_sampled() {
+        let trace_parent = TraceParent::for_request(&HeaderMap::new());
+        assert_ne!(trace_parent.trace_id, 0);
+        assert!(trace_parent.sampled);
+        assert!(TraceParent::parse(&trace_parent.to_string()).is_some());
+    }
+}
@@ -0,0 +1,109 @@
+use crate::state::SharedState;
+use axum::extract::State;
+use time::{format_description::well_known::Rfc3339, Duration, OffsetDateTime};
+
+/// Keeps every crawler out, there's nothing to index.
+pub const DEFAULT_ROBOTS_TXT: &str = "User-agent: *\nDisallow: /\n";
+const SECURITY_CONTACT: &str = "https://github.com/robertohuertasm/shuttle-datadog-logs/security";
+/// How far in the future the default `security.txt` expires. RFC 9116 recommends less than a
+/// year.
+const SECURITY_TXT_EXPIRY: Duration = Duration::days(180);
+
+/// `security.txt` with the [`SECURITY_CONTACT`] and the required `Expires` field, which is
+/// always [`SECURITY_TXT_EXPIRY`] away from `now` so it never goes stale.
+pub fn default_security_txt(now: OffsetDateTime) -> String {
+    let expires = (now + SECURITY_TXT_EXPIRY)
+        .replace_nanosecond(0)
+        .unwrap_or(now)
+        .format(&Rfc3339)
+        .unwrap_or_default();
+    format!("Contact: {SECURITY_CONTACT}\nExpires: {expires}\n")
+}
+
+/// `/robots.txt`, from `ROBOTS_TXT` or [`DEFAULT_ROBOTS_TXT`].
+pub async fn robots_txt(State(state): State<SharedState>) -> String {
+    state
+        .config()
+        .robots_txt
+        .unwrap_or_else(|| DEFAULT_ROBOTS_TXT.to_string())
+}
+
+/// `/.well-known/security.txt`, from `SECURITY_TXT` or [`default_security_txt`].
+pub async fn security_txt(State(state): State<SharedState>) -> String {
+    state
+        .config()
+        .security_txt
+        .unwrap_or_else(|| default_security_txt(OffsetDateTime::now_utc()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{self, body_string, send};
+    use axum::{
+        body::Body,
+        http::{header, Request, StatusCode},
+    };
+
+    async fn get(pairs: &[(&str, &str)], uri: &str) -> (StatusCode, String, String) {
+        let test = test_support::state(pairs);
+        let req = Request::get(uri).body(Body::empty()).unwrap();
+        let response = send(test.router(), req).await;
+        let content_type = response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_string();
+        (response.status(), content_type, body_string(response).await)
+    }
+
+    #[tokio::test]
+    async fn default_robots_txt_disallows_everything() {
+        let (status, content_type, body) = get(&[], "/robots.txt").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type, "text/plain; charset=utf-8");
+        assert_eq!(body, DEFAULT_ROBOTS_TXT);
+    }
+
+    #[tokio::test]
+    async fn default_security_txt_has_a_contact_and_expires() {
+        let (status, content_type, body) = get(&[], "/.well-known/security.txt").await;
+
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type, "text/plain; charset=utf-8");
+        assert!(body.contains(&format!("Contact: {SECURITY_CONTACT}\n")));
+        let expires = body
+            .lines()
+            .find_map(|line| line.strip_prefix("Expires: "))
+            .expect("no Expires field");
+        let expires = OffsetDateTime::parse(expires, &Rfc3339).unwrap();
+        let now = OffsetDateTime::now_utc();
+        assert!(expires > now);
+        assert!(expires < now + Duration::days(365));
+    }
+
+    #[test]
+    fn default_security_txt_expires_180_days_later() {
+        let now = OffsetDateTime::from_unix_timestamp(1_700_000_000).unwrap();
+
+        assert_eq!(
+            default_security_txt(now),
+            format!("Contact: {SECURITY_CONTACT}\nExpires: 2024-05-12T22:13:20Z\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn files_can_be_configured() {
+        let robots_txt = "User-agent: *\nAllow: /\n";
+        let (_, _, body) = get(&[("ROBOTS_TXT", robots_txt)], "/robots.txt").await;
+        assert_eq!(body, robots_txt);
+
+        let security_txt = "Contact: mailto:security@example.com\n";
+        let (_, _, body) = get(
+            &[("SECURITY_TXT", security_txt)],
+            "/.well-known/security.txt",
+        )
+        .await;
+        assert_eq!(body, security_txt);
+    }
+}